@@ -0,0 +1,21 @@
+//! Attributes declaring contracts on Rust functions, which testify
+//! collects from the sources of a crate. They expand to nothing.
+
+use proc_macro::TokenStream;
+
+/// Declares a contract on the function it is attached to, e.g.:
+///
+/// ```ignore
+/// #[testify::contract(pre = "x < 127", post = "double(x) == x + x")]
+/// fn double(x: u8) -> u8 {
+///     x * 2
+/// }
+/// ```
+///
+/// Besides `pre` and `post`, the keys `description`, `name`, `tags`,
+/// `tests`, `seed` and `strategy` are those of contracts files. The
+/// function is left unchanged.
+#[proc_macro_attribute]
+pub fn contract(_args: TokenStream, item: TokenStream) -> TokenStream {
+    item
+}
@@ -0,0 +1,770 @@
+//! This module collects the contracts declared on the functions of a
+//! crate, with the `#[testify::contract(...)]` attribute (see the
+//! `testify-macros` crate) or in fenced `testify` blocks of their
+//! documentation. The modules of the crate are walked from its
+//! library root, and each declaration yields a contract whose tested
+//! function, inputs and dependencies are derived from the function
+//! and the crate, and whose spans point at the declaration.
+use crate::contracts_file::Problem;
+use crate::prelude::*;
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+
+/// The library of a crate.
+struct Library {
+    /// The name of the package of the crate.
+    package: String,
+    /// The name of the library, as used in paths.
+    name: String,
+    /// The directory of the crate.
+    dir: PathBuf,
+}
+
+impl Library {
+    /// Reads the manifest of the crate at `dir`, returning the library
+    /// and its root file. Returns `None` when `dir` is not a package.
+    fn read(dir: &Path) -> Result<Option<(Self, PathBuf)>, String> {
+        let manifest_path = dir.join("Cargo.toml");
+        let manifest: toml::Table = toml::from_str(
+            &std::fs::read_to_string(&manifest_path).map_err(|err| err.to_string())?,
+        )
+        .map_err(|err| format!("{}: {}", manifest_path.display(), err.message()))?;
+        let Some(package) = manifest
+            .get("package")
+            .and_then(|package| package.get("name"))
+            .and_then(toml::Value::as_str)
+        else {
+            return Ok(None);
+        };
+        let lib = manifest.get("lib");
+        let field = |field| {
+            lib.and_then(|lib| lib.get(field))
+                .and_then(toml::Value::as_str)
+        };
+        let name = field("name").map_or(package.replace('-', "_"), str::to_string);
+        let root = dir.join(field("path").unwrap_or("src/lib.rs"));
+        let library = Self {
+            package: package.to_string(),
+            name,
+            dir: dir.to_path_buf(),
+        };
+        Ok(Some((library, root)))
+    }
+
+    /// The dependency on this library.
+    fn dependency(&self) -> toml::Table {
+        let mut spec = toml::Table::new();
+        spec.insert("path".into(), self.dir.display().to_string().into());
+        if self.package != self.name {
+            spec.insert("package".into(), self.package.clone().into());
+        }
+        toml::Table::from_iter([(self.name.clone(), spec.into())])
+    }
+}
+
+/// Whether `attribute` is the `contract` attribute.
+fn is_contract(attribute: &syn::Attribute) -> bool {
+    let segments: Vec<_> = attribute
+        .path()
+        .segments
+        .iter()
+        .map(|segment| segment.ident.to_string())
+        .collect();
+    matches!(
+        segments.iter().map(String::as_str).collect::<Vec<_>>()[..],
+        ["contract"] | ["testify" | "testify_macros", "contract"]
+    )
+}
+
+/// Collects the contracts of a file.
+struct Collector<'a> {
+    library: &'a Library,
+    file: PathBuf,
+    source: String,
+    contracts: Vec<Contract>,
+    problems: Vec<Problem>,
+}
+
+impl Collector<'_> {
+    /// The span of `span` in the file.
+    fn span(&self, span: proc_macro2::Span) -> Span {
+        Span::from_range(&self.source, Some(self.file.clone()), span.byte_range())
+    }
+
+    fn problem(&mut self, span: proc_macro2::Span, message: impl ToString) {
+        let problem = self.located(span, message);
+        self.problems.push(problem);
+    }
+
+    /// Resolves `path`, written in `module`, to a path starting with
+    /// the name of the library. Paths which are neither relative to
+    /// `crate`, `self` or `super` are expected to be defined in
+    /// `module`.
+    fn resolve(&self, module: &[String], path: &syn::Path) -> String {
+        let mut resolved = vec![self.library.name.clone()];
+        let mut segments = path.segments.iter().map(|s| s.ident.to_string()).peekable();
+        match segments.peek().map(String::as_str) {
+            Some("crate") => {
+                segments.next();
+            }
+            Some("self") => {
+                segments.next();
+                resolved.extend(module.iter().cloned());
+            }
+            Some("super") => {
+                let mut module = module.to_vec();
+                while segments.next_if(|segment| segment == "super").is_some() {
+                    module.pop();
+                }
+                resolved.extend(module);
+            }
+            _ => resolved.extend(module.iter().cloned()),
+        }
+        resolved.extend(segments);
+        resolved.join("::")
+    }
+
+    /// Collects the contracts of `items`, which belong to `module`.
+    /// `public` tells whether `module` is reachable from outside the
+    /// crate. The files of the modules they declare are found in
+    /// `dir`, and `#[path]` attributes are relative to `file_dir`.
+    fn items(
+        &mut self,
+        items: &[syn::Item],
+        module: &[String],
+        public: bool,
+        dir: &Path,
+        file_dir: &Path,
+    ) {
+        for item in items {
+            match item {
+                syn::Item::Fn(function) => self.function(
+                    &function.attrs,
+                    &function.sig,
+                    module,
+                    public && is_public(&function.vis),
+                    None,
+                ),
+                syn::Item::Impl(block) => self.impl_block(block, module, public),
+                syn::Item::Mod(item) => {
+                    let name = item.ident.to_string();
+                    let module = [module, std::slice::from_ref(&name)].concat();
+                    let public = public && is_public(&item.vis);
+                    match &item.content {
+                        Some((_, items)) => {
+                            self.items(items, &module, public, &dir.join(&name), &dir.join(&name))
+                        }
+                        None => {
+                            let path = item.attrs.iter().find_map(|attribute| {
+                                let syn::Meta::NameValue(meta) = &attribute.meta else {
+                                    return None;
+                                };
+                                let syn::Expr::Lit(syn::ExprLit {
+                                    lit: syn::Lit::Str(path),
+                                    ..
+                                }) = &meta.value
+                                else {
+                                    return None;
+                                };
+                                meta.path
+                                    .is_ident("path")
+                                    .then(|| file_dir.join(path.value()))
+                            });
+                            let file = path.into_iter().chain([
+                                dir.join(format!("{name}.rs")),
+                                dir.join(&name).join("mod.rs"),
+                            ]);
+                            // Modules without a file are disabled by `cfg`
+                            if let Some(file) = file.into_iter().find(|file| file.is_file()) {
+                                collect_file(
+                                    self.library,
+                                    &file,
+                                    &module,
+                                    public,
+                                    &dir.join(&name),
+                                )
+                                .map(|(contracts, problems)| {
+                                    self.contracts.extend(contracts);
+                                    self.problems.extend(problems);
+                                })
+                                .unwrap_or_else(|err| self.problem(item.span(), err));
+                            }
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Collects the contracts of the methods of an `impl` block of
+    /// `module` (see `items`).
+    fn impl_block(&mut self, block: &syn::ItemImpl, module: &[String], public: bool) {
+        let functions: Vec<_> = block
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                syn::ImplItem::Fn(function) => Some(function),
+                _ => None,
+            })
+            .filter(|function| {
+                function.attrs.iter().any(is_contract) || !doc_blocks(&function.attrs).is_empty()
+            })
+            .collect();
+        if functions.is_empty() {
+            return;
+        }
+        let syn::Type::Path(self_ty) = block.self_ty.as_ref() else {
+            return self.problem(
+                block.self_ty.span(),
+                "contracts are only supported on methods of named types",
+            );
+        };
+        // Methods are named after the module of their `impl` block and
+        // the name of their type (see `TestedFunction::of_item`)
+        let ty_name = &self_ty.path.segments.last().unwrap().ident;
+        let ty_path = self.resolve(module, &ty_name.clone().into());
+        let prefix = match &block.trait_ {
+            Some((_, trait_path, _)) => {
+                let trait_name = &trait_path.segments.last().unwrap().ident;
+                format!("<{ty_path} as {trait_name}>")
+            }
+            None => ty_path,
+        };
+        let method = Method {
+            prefix,
+            self_ty: self.resolve(module, &self_ty.path),
+        };
+        for function in functions {
+            // The methods of trait implementations are as visible as
+            // their trait
+            let public = public && (block.trait_.is_some() || is_public(&function.vis));
+            self.function(
+                &function.attrs,
+                &function.sig,
+                module,
+                public,
+                Some(&method),
+            );
+        }
+    }
+
+    /// The problem `message`, located at `span`.
+    fn located(&self, span: proc_macro2::Span, message: impl ToString) -> Problem {
+        Problem::new(None, None, message).with_span(Some(&self.span(span)))
+    }
+
+    /// Collects the contracts declared by `attributes` (`contract`
+    /// attributes, or `testify` blocks in documentation) on the
+    /// function `signature`, defined in `module`, or a `method` of an
+    /// `impl` block of `module`. `public` tells whether the function
+    /// is reachable from outside the crate.
+    fn function(
+        &mut self,
+        attributes: &[syn::Attribute],
+        signature: &syn::Signature,
+        module: &[String],
+        public: bool,
+        method: Option<&Method>,
+    ) {
+        let declarations = attributes
+            .iter()
+            .filter(|attribute| is_contract(attribute))
+            .map(|attribute| self.attribute(attribute))
+            .chain(self.doc_blocks(attributes))
+            .collect::<Vec<_>>();
+        for declaration in declarations {
+            match declaration.and_then(|declaration| {
+                self.contract(declaration, signature, module, public, method)
+            }) {
+                Ok(contract) => self.contracts.push(contract),
+                Err(problem) => self.problems.push(problem),
+            }
+        }
+    }
+
+    /// Reads the keys of a `contract` attribute.
+    fn attribute(&self, attribute: &syn::Attribute) -> Result<Declaration, Problem> {
+        let mut table = toml::Table::new();
+        let mut field_spans = HashMap::new();
+        let arguments = attribute
+            .parse_args_with(Punctuated::<syn::MetaNameValue, syn::Token![,]>::parse_terminated)
+            .map_err(|err| self.located(err.span(), err))?;
+        for argument in arguments {
+            let key = argument.path.get_ident().map(|key| key.to_string());
+            let field = match key.as_deref() {
+                Some("pre") => "precondition",
+                Some("post") => "postcondition",
+                Some(key @ ("description" | "name" | "tags" | "tests" | "seed" | "strategy")) => {
+                    key
+                }
+                _ => {
+                    return Err(self.located(
+                        argument.path.span(),
+                        format!("unknown key `{}`", argument.path.to_token_stream()),
+                    ))
+                }
+            };
+            let value = match &argument.value {
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(string),
+                    ..
+                }) => string.value().into(),
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Int(int),
+                    ..
+                }) => int
+                    .base10_parse::<i64>()
+                    .map_err(|err| self.located(int.span(), err))?
+                    .into(),
+                syn::Expr::Array(array) => array
+                    .elems
+                    .iter()
+                    .map(|element| match element {
+                        syn::Expr::Lit(syn::ExprLit {
+                            lit: syn::Lit::Str(string),
+                            ..
+                        }) => Ok(string.value()),
+                        element => Err(self.located(element.span(), "expected a string")),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+                    .into(),
+                value => return Err(self.located(value.span(), "expected a literal")),
+            };
+            field_spans.insert(field.to_string(), self.span(argument.value.span()));
+            table.insert(field.to_string(), value);
+        }
+        Ok(Declaration {
+            table,
+            field_spans,
+            span: self.span(attribute.span()),
+        })
+    }
+
+    /// Reads the `testify` blocks of the documentation `attributes`
+    /// (see `doc_blocks`). Fields are located at the line of the
+    /// documentation they start on.
+    fn doc_blocks(&self, attributes: &[syn::Attribute]) -> Vec<Result<Declaration, Problem>> {
+        doc_blocks(attributes)
+            .into_iter()
+            .map(|DocBlock { text, fence, lines }| {
+                let span = |offset: usize| {
+                    let line = text[..offset.min(text.len())].matches('\n').count();
+                    self.span(lines.get(line).unwrap_or(&fence).span())
+                };
+                let fields: HashMap<String, toml::Spanned<toml::Value>> = toml::from_str(&text)
+                    .map_err(|err| {
+                        let offset = err.span().map_or(0, |range| range.start);
+                        Problem::new(None, None, err.message()).with_span(Some(&span(offset)))
+                    })?;
+                let field_spans = fields
+                    .iter()
+                    .map(|(field, value)| (field.clone(), span(value.span().start)))
+                    .collect();
+                let table = fields
+                    .into_iter()
+                    .map(|(field, value)| (field, value.into_inner()))
+                    .collect();
+                Ok(Declaration {
+                    table,
+                    field_spans,
+                    span: self.span(fence.span()),
+                })
+            })
+            .collect()
+    }
+
+    /// Turns `declaration`, on the function `signature` (see
+    /// `function`), into a contract. The tested function, the
+    /// dependency on the library and a `use` of `module` (and of the
+    /// type of `method`) are added, as well as inputs and a
+    /// description when they are not declared. Contracts are evaluated
+    /// outside the crate, so functions which are not `public` are
+    /// reported.
+    fn contract(
+        &self,
+        declaration: Declaration,
+        signature: &syn::Signature,
+        module: &[String],
+        public: bool,
+        method: Option<&Method>,
+    ) -> Result<Contract, Problem> {
+        let Declaration {
+            mut table,
+            field_spans,
+            span,
+        } = declaration;
+        let located = |field: &str, message: String| {
+            let span = field_spans.get(field).unwrap_or(&span);
+            Problem::new(None, Some(field.to_string()), message).with_span(Some(span))
+        };
+        for field in ["precondition", "postcondition"] {
+            if let Some(toml::Value::String(expr)) = table.get(field) {
+                syn::parse_str::<syn::Expr>(expr).map_err(|err| located(field, err.to_string()))?;
+            }
+        }
+        let function = match method {
+            Some(method) => format!("{}::{}", method.prefix, signature.ident),
+            None => self.resolve(module, &signature.ident.clone().into()),
+        };
+        if !public {
+            return Err(Problem::new(
+                None,
+                None,
+                format!(
+                    "`{function}` is not reachable from outside the crate: \
+                     contracts can only be declared on public functions of public modules"
+                ),
+            )
+            .with_span(Some(&span)));
+        }
+        table
+            .entry("description")
+            .or_insert(function.clone().into());
+        if !table.contains_key("inputs") {
+            let inputs =
+                inputs(signature).map_err(|(span, message)| self.located(span, message))?;
+            let inputs =
+                toml::Value::try_from(inputs).map_err(|err| located("inputs", err.to_string()))?;
+            table.insert("inputs".into(), inputs);
+        }
+        table.insert("function_tested".into(), function.into());
+        if let toml::Value::Table(dependencies) = table
+            .entry("dependencies")
+            .or_insert(toml::Table::new().into())
+        {
+            for (name, spec) in self.library.dependency() {
+                dependencies.entry(name).or_insert(spec);
+            }
+        }
+        let glob = [
+            std::slice::from_ref(&self.library.name),
+            module,
+            &["*".to_string()],
+        ]
+        .concat();
+        if let toml::Value::Array(use_statements) = table
+            .entry("use_statements")
+            .or_insert(toml::Value::Array(vec![]))
+        {
+            use_statements.push(glob.join("::").into());
+            if let Some(method) = method {
+                use_statements.push(method.self_ty.clone().into());
+            }
+        }
+
+        let mut contract: Contract =
+            toml::Value::Table(table)
+                .try_into()
+                .map_err(|err: toml::de::Error| {
+                    Problem::new(None, None, err.message()).with_span(Some(&span))
+                })?;
+        contract.span = span;
+        contract.field_spans = field_spans;
+        Ok(contract)
+    }
+}
+
+/// A method of an `impl` block.
+struct Method {
+    /// The path of the method, without its name.
+    prefix: String,
+    /// The resolved path of the type of the `impl` block.
+    self_ty: String,
+}
+
+/// Whether `visibility` makes an item reachable from outside the
+/// crate.
+fn is_public(visibility: &syn::Visibility) -> bool {
+    matches!(visibility, syn::Visibility::Public(_))
+}
+
+/// A contract declared on a function, by a `contract` attribute or in
+/// its documentation, before the keys derived from the function are
+/// added.
+struct Declaration {
+    table: toml::Table,
+    field_spans: HashMap<String, Span>,
+    span: Span,
+}
+
+/// A fenced `testify` block of documentation.
+struct DocBlock<'a> {
+    /// The text of the block, without its fences.
+    text: String,
+    /// The attribute the opening fence comes from.
+    fence: &'a syn::Attribute,
+    /// The attribute each line of the text comes from.
+    lines: Vec<&'a syn::Attribute>,
+}
+
+/// Finds the fenced `testify` blocks of the documentation
+/// `attributes`. As in rustdoc, a block which is not closed runs to
+/// the end of the documentation.
+fn doc_blocks(attributes: &[syn::Attribute]) -> Vec<DocBlock<'_>> {
+    let lines = attributes.iter().flat_map(|attribute| {
+        let syn::Meta::NameValue(meta) = &attribute.meta else {
+            return vec![];
+        };
+        let syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Str(doc),
+            ..
+        }) = &meta.value
+        else {
+            return vec![];
+        };
+        if !meta.path.is_ident("doc") {
+            return vec![];
+        }
+        doc.value()
+            .lines()
+            .map(|line| (line.to_string(), attribute))
+            .collect()
+    });
+    let mut blocks = vec![];
+    // The block being read, if any, along with its fence
+    let mut block: Option<(String, DocBlock)> = None;
+    for (line, attribute) in lines {
+        let trimmed = line.trim_start();
+        match &mut block {
+            None => {
+                let fence = trimmed.len() - trimmed.trim_start_matches(['`', '~']).len();
+                let info = &trimmed[fence..];
+                if fence >= 3 && info.split(',').any(|lang| lang.trim() == "testify") {
+                    let doc_block = DocBlock {
+                        text: String::new(),
+                        fence: attribute,
+                        lines: vec![],
+                    };
+                    block = Some((trimmed[..fence].to_string(), doc_block));
+                }
+            }
+            Some((fence, _)) if trimmed.trim_end() == fence => {
+                blocks.extend(block.take().map(|(_, doc_block)| doc_block));
+            }
+            Some((_, doc_block)) => {
+                doc_block.text += &line;
+                doc_block.text += "\n";
+                doc_block.lines.push(attribute);
+            }
+        }
+    }
+    blocks.extend(block.map(|(_, doc_block)| doc_block));
+    blocks
+}
+
+/// The inputs of a contract on the function `signature`: its
+/// arguments, and its type parameters along with their bounds.
+fn inputs(signature: &syn::Signature) -> Result<Vec<Input>, (proc_macro2::Span, String)> {
+    let mut inputs = vec![];
+    for param in signature.generics.type_params() {
+        let ident = &param.ident;
+        let bounds = &param.bounds;
+        let mut bounds: syn::WhereClause = parse_quote! {where #ident: #bounds};
+        let predicates = signature.generics.where_clause.iter();
+        bounds.predicates.extend(
+            predicates
+                .flat_map(|clause| clause.predicates.iter())
+                .filter(|predicate| match predicate {
+                    syn::WherePredicate::Type(predicate) => {
+                        predicate.bounded_ty == parse_quote! {#ident}
+                    }
+                    _ => false,
+                })
+                .cloned(),
+        );
+        inputs.push(Input {
+            name: ident.to_string(),
+            kind: InputKind::Type { bounds },
+        });
+    }
+    for argument in &signature.inputs {
+        let syn::FnArg::Typed(argument) = argument else {
+            return Err((
+                argument.span(),
+                "contracts on methods taking `self` are not supported yet".into(),
+            ));
+        };
+        let syn::Pat::Ident(pat) = argument.pat.as_ref() else {
+            return Err((
+                argument.pat.span(),
+                "expected an identifier: contracts refer to arguments by name".into(),
+            ));
+        };
+        inputs.push(Input {
+            name: pat.ident.to_string(),
+            kind: InputKind::Value {
+                typ: argument.ty.as_ref().clone(),
+                aliases: vec![],
+            },
+        });
+    }
+    Ok(inputs)
+}
+
+/// Collects the contracts of `file`, the module `module` of
+/// `library`, whose submodules are found in `dir`. `public` tells
+/// whether `module` is reachable from outside the crate.
+fn collect_file(
+    library: &Library,
+    file: &Path,
+    module: &[String],
+    public: bool,
+    dir: &Path,
+) -> Result<(Vec<Contract>, Vec<Problem>), String> {
+    let source = std::fs::read_to_string(file)
+        .map_err(|err| format!("could not read {}: {err}", file.display()))?;
+    let mut collector = Collector {
+        library,
+        file: file.to_path_buf(),
+        source,
+        contracts: vec![],
+        problems: vec![],
+    };
+    let items = match syn::parse_file(&collector.source) {
+        Ok(parsed) => parsed.items,
+        Err(err) => {
+            collector.problem(err.span(), err);
+            vec![]
+        }
+    };
+    let file_dir = file.parent().unwrap_or(Path::new(""));
+    collector.items(&items, module, public, dir, file_dir);
+    Ok((collector.contracts, collector.problems))
+}
+
+/// Collects the contracts declared on the functions of the library of
+/// the crate at `dir`. Returns no contract when `dir` is not a package
+/// (e.g. a virtual workspace) or has no library.
+pub fn collect(dir: &Path) -> Result<Vec<Contract>, Vec<Problem>> {
+    let problem = |message| vec![Problem::new(None, None, message)];
+    let (library, root) = match Library::read(dir) {
+        Ok(Some(library)) => library,
+        Ok(None) => return Ok(vec![]),
+        Err(err) => return Err(problem(err)),
+    };
+    if !root.is_file() {
+        return Ok(vec![]);
+    }
+    // The submodules of the root are next to it
+    let root_dir = root.parent().unwrap_or(Path::new("")).to_path_buf();
+    let (contracts, problems) =
+        collect_file(&library, &root, &[], true, &root_dir).map_err(problem)?;
+    match problems.is_empty() {
+        true => Ok(contracts),
+        false => Err(problems),
+    }
+}
+
+#[test]
+fn collect_attributes() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("src/ops")).unwrap();
+    std::fs::write(
+        dir.path().join("Cargo.toml"),
+        "[package]\nname = \"my-crate\"\nversion = \"0.1.0\"\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.path().join("src/lib.rs"),
+        "pub mod ops;\npub struct S;\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.path().join("src/ops/mod.rs"),
+        r#"
+pub mod nested {
+    impl super::super::S {
+        #[testify::contract(pre = "x < 127", post = "S::double(x) == x + x", tags = ["math"])]
+        pub fn double(x: u8) -> u8 { x * 2 }
+    }
+}
+
+#[contract(post = "id(x) == x", tests = 3)]
+pub fn id<T: Clone>(x: T) -> T where T: PartialEq { x }
+
+/// Halves `x`, rounding down.
+///
+/// ```testify
+/// description = "halving doubles"
+/// postcondition = "half(x) * 2 <= x"
+/// ```
+pub fn half(x: u8) -> u8 { x / 2 }
+"#,
+    )
+    .unwrap();
+    let contracts = collect(dir.path()).unwrap();
+    let functions: Vec<_> = contracts
+        .iter()
+        .map(|contract| {
+            crate::TestedFunction::new(contract.function_tested.as_ref().unwrap())
+                .unwrap()
+                .to_string()
+        })
+        .collect();
+    assert_eq!(
+        functions,
+        [
+            "my_crate::ops::nested::S::double",
+            "my_crate::ops::id",
+            "my_crate::ops::half"
+        ]
+    );
+    let (double, id, half) = (&contracts[0], &contracts[1], &contracts[2]);
+    assert_eq!(double.tags, ["math"]);
+    assert_eq!(double.inputs.len(), 1);
+    assert_eq!(
+        double.span.to_string(),
+        format!("{}:4:9", dir.path().join("src/ops/mod.rs").display())
+    );
+    assert_eq!(double.field_spans["precondition"].start.line, 4);
+    assert_eq!(id.tests, 3);
+    let bounds = match &id.inputs[0].kind {
+        InputKind::Type { bounds } => bounds.to_token_stream().to_string(),
+        InputKind::Value { .. } => panic!("expected a type input"),
+    };
+    assert_eq!(bounds, "where T : Clone , T : PartialEq");
+    assert_eq!(half.description, "halving doubles");
+    assert_eq!(half.inputs[0].name, "x");
+    assert_eq!(half.span.start.line, 14);
+    assert_eq!(half.field_spans["postcondition"].start.line, 16);
+    assert_eq!(
+        id.use_statements[0].to_token_stream().to_string(),
+        "my_crate :: ops :: *"
+    );
+    let uses: Vec<_> = double
+        .use_statements
+        .iter()
+        .map(|use_statement| use_statement.to_token_stream().to_string())
+        .collect();
+    assert_eq!(uses, ["my_crate :: ops :: nested :: *", "my_crate :: S"]);
+}
+
+#[test]
+fn collect_private_functions() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("src")).unwrap();
+    std::fs::write(
+        dir.path().join("Cargo.toml"),
+        "[package]\nname = \"my-crate\"\nversion = \"0.1.0\"\n",
+    )
+    .unwrap();
+    std::fs::write(dir.path().join("src/lib.rs"), "mod ops;\n").unwrap();
+    std::fs::write(
+        dir.path().join("src/ops.rs"),
+        "#[contract(post = \"id(x) == x\")]\npub fn id(x: u8) -> u8 { x }\n",
+    )
+    .unwrap();
+    let problems = collect(dir.path()).unwrap_err();
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].to_string().contains("`my_crate::ops::id`"));
+
+    // Public modules with private functions are reported as well
+    std::fs::write(dir.path().join("src/lib.rs"), "pub mod ops;\n").unwrap();
+    std::fs::write(
+        dir.path().join("src/ops.rs"),
+        "#[contract(post = \"id(x) == x\")]\nfn id(x: u8) -> u8 { x }\n",
+    )
+    .unwrap();
+    assert_eq!(collect(dir.path()).unwrap_err().len(), 1);
+}
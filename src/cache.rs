@@ -0,0 +1,200 @@
+//! This module provides a persistent, content-addressed cache, so
+//! that runs don't rebuild what previous runs already built. Entries
+//! are keyed on the generated source of a crate, on its dependencies
+//! (including the contents of path dependencies) and on the versions
+//! of the tools, and live in `$XDG_CACHE_HOME/testify` (by default
+//! `~/.cache/testify`), in one directory per kind of entry:
+//!  - `hax`: the output of hax on the crates answering hax queries;
+//!  - `eval`: the values of evaluated `eval` nodes;
+//!  - `servers`: compiled precondition server binaries.
+use crate::prelude::*;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Disables the cache for the rest of the run: entries are neither
+/// read nor written.
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed)
+}
+
+/// Whether the cache is enabled.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// The directory of the cache.
+pub fn cache_dir() -> PathBuf {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(std::env::var_os("HOME")?).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("testify")
+}
+
+/// Removes every entry of the cache.
+pub fn clean() -> std::io::Result<()> {
+    match fs::remove_dir_all(cache_dir()) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// The key of a cache entry.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Key([u8; 32]);
+
+/// The versions of the tools the entries depend on: `rustc` and hax.
+fn tool_versions() -> &'static str {
+    static VERSIONS: OnceLock<String> = OnceLock::new();
+    VERSIONS.get_or_init(|| {
+        let version = |program: &str, args: &[&str]| {
+            std::process::Command::new(program)
+                .args(args)
+                .output()
+                .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+                .unwrap_or_default()
+        };
+        version("rustc", &["-vV"]) + &version("cargo", &["hax", "--version"])
+    })
+}
+
+/// Feeds `hasher` with the contents of the crate at `dir`: its
+/// manifest and its Rust sources (skipping `target` and hidden
+/// directories), and, recursively, its own path dependencies.
+fn hash_crate(dir: &Path, hasher: &mut Sha256, visited: &mut HashSet<PathBuf>) {
+    let dir = fs::canonicalize(dir).unwrap_or(dir.to_path_buf());
+    if !visited.insert(dir.clone()) {
+        return;
+    }
+    fn files(dir: &Path, found: &mut Vec<PathBuf>) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for path in entries.flatten().map(|entry| entry.path()) {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if path.is_dir() {
+                if !name.starts_with('.') && name != "target" {
+                    files(&path, found);
+                }
+            } else if name.ends_with(".rs") || name == "Cargo.toml" {
+                found.push(path);
+            }
+        }
+    }
+    let mut found = vec![];
+    files(&dir, &mut found);
+    found.sort();
+    for file in found {
+        hasher.update(
+            file.strip_prefix(&dir)
+                .unwrap()
+                .to_string_lossy()
+                .as_bytes(),
+        );
+        hasher.update(fs::read(&file).unwrap_or_default());
+    }
+    let manifest: Option<toml::Table> = fs::read_to_string(dir.join("Cargo.toml"))
+        .ok()
+        .and_then(|manifest| toml::from_str(&manifest).ok());
+    let dependencies = manifest
+        .iter()
+        .flat_map(|manifest| manifest.get("dependencies").and_then(toml::Value::as_table))
+        .flat_map(|dependencies| dependencies.values());
+    for dependency in dependencies {
+        if let Some(path) = dependency.get("path").and_then(toml::Value::as_str) {
+            hash_crate(&dir.join(path), hasher, visited);
+        }
+    }
+}
+
+impl Key {
+    /// Computes the key of a crate out of its generated source and
+    /// its dependencies. The contents of path dependencies are part of
+    /// the key, so that editing the crate under test invalidates its
+    /// entries, and so are the versions of testify, `rustc` and hax.
+    pub fn new(source: &str, dependencies: &HashMap<String, DependencySpec>) -> Self {
+        let dependencies: BTreeMap<_, _> = dependencies.iter().collect();
+        let mut hasher = Sha256::new();
+        hasher.update(env!("CARGO_PKG_VERSION"));
+        hasher.update(tool_versions());
+        hasher.update(source);
+        hasher.update(serde_json::to_string(&dependencies).unwrap());
+        let mut visited = HashSet::new();
+        for spec in dependencies.values() {
+            if let Some(path) = spec.path() {
+                hash_crate(&path, &mut hasher, &mut visited);
+            }
+        }
+        Self(hasher.finalize().into())
+    }
+
+    /// The path of the entry of kind `kind` for this key.
+    pub fn path(&self, kind: &str) -> PathBuf {
+        let name: String = self.0.iter().map(|byte| format!("{byte:02x}")).collect();
+        cache_dir().join(kind).join(name)
+    }
+}
+
+/// Reads the entry of kind `kind` for `key`, if any.
+pub fn get<T: serde::de::DeserializeOwned>(kind: &str, key: Key) -> Option<T> {
+    if !enabled() {
+        return None;
+    }
+    let contents = fs::read_to_string(key.path(kind)).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Writes `value` as the entry of kind `kind` for `key`.
+pub fn put<T: Serialize>(kind: &str, key: Key, value: &T) {
+    if !enabled() {
+        return;
+    }
+    let _ = store(
+        &key.path(kind),
+        serde_json::to_string(value).unwrap().as_bytes(),
+    );
+}
+
+/// Writes `contents` at `path`, atomically: concurrent runs never
+/// observe partially written entries.
+pub fn store(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let dir = path.parent().unwrap();
+    fs::create_dir_all(dir)?;
+    let tmp = tempfile::NamedTempFile::new_in(dir)?;
+    fs::write(tmp.path(), contents)?;
+    tmp.persist(path)?;
+    Ok(())
+}
+
+/// Copies the file `source` at `path`, atomically. Permissions are
+/// preserved, so that binaries stay executable.
+pub fn store_file(path: &Path, source: &Path) -> std::io::Result<()> {
+    let dir = path.parent().unwrap();
+    fs::create_dir_all(dir)?;
+    let tmp = tempfile::NamedTempFile::new_in(dir)?;
+    fs::copy(source, tmp.path())?;
+    tmp.persist(path)?;
+    Ok(())
+}
+
+#[test]
+fn key_depends_on_path_dependencies() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("src")).unwrap();
+    fs::write(dir.path().join("Cargo.toml"), "[package]\nname = \"k\"").unwrap();
+    fs::write(dir.path().join("src/lib.rs"), "pub fn f() {}").unwrap();
+    let spec = toml::Value::Table(toml::Table::from_iter([(
+        "path".to_string(),
+        dir.path().display().to_string().into(),
+    )]));
+    let dependencies = HashMap::from_iter([("k".to_string(), DependencySpec(spec))]);
+    let key = Key::new("fn main() {}", &dependencies);
+    assert_eq!(key, Key::new("fn main() {}", &dependencies));
+    fs::write(dir.path().join("src/lib.rs"), "pub fn f() { todo!() }").unwrap();
+    assert_ne!(key, Key::new("fn main() {}", &dependencies));
+}
@@ -0,0 +1,923 @@
+//! This module reads contracts files (following their includes, or
+//! discovering them in directories), and checks contracts without
+//! generating tests: syntax errors are reported with the index of
+//! the contract and the field at fault, free identifiers are
+//! flagged, and contracts are type-checked with one `cargo check`
+//! pass per pool.
+use crate::krate::{
+    diagnostics::{locate_all, mark},
+    Krate,
+};
+use crate::pool::group_by_dependencies;
+use crate::prelude::*;
+use crate::TestedFunction;
+use std::fmt;
+
+/// The suffix of the names of the contracts files discovered in
+/// directories.
+pub const EXTENSION: &str = ".contracts.toml";
+
+/// A TOML file declaring a list of contracts.
+#[derive(fmt_derive::Debug, Clone, Serialize, Deserialize)]
+pub struct ContractsFile {
+    /// Other contracts files (or directories of contracts files) to
+    /// read, relative to this file.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<PathBuf>,
+    /// Keys shared by the contracts of this file.
+    #[serde(default, skip_serializing_if = "Defaults::is_empty")]
+    pub defaults: Defaults,
+    /// The contracts of this file, with `defaults` merged in.
+    #[serde(default)]
+    pub contracts: Vec<Contract>,
+}
+
+/// The `[defaults]` table of a contracts file, merged into each of
+/// its contracts. Contracts override the keys they set, except that
+/// dependencies are merged by name and `use_statements` are added to
+/// the default ones.
+#[derive(fmt_derive::Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Defaults {
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub dependencies: HashMap<String, DependencySpec>,
+    #[serde(with = "serde_via::SerdeVia")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub use_statements: Vec<syn::UseTree>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tests: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<Strategy>,
+    /// A path prepended to the `function_tested` of contracts, unless
+    /// it starts with `::` (e.g. `::other_crate::f`) or is qualified
+    /// (e.g. `<Type as Trait>::method`).
+    #[debug("{:?}", function_tested_prefix.as_ref().map(|path| path.to_token_stream().to_string()))]
+    #[serde(with = "serde_via::SerdeVia")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function_tested_prefix: Option<syn::Path>,
+}
+
+impl Defaults {
+    pub fn is_empty(&self) -> bool {
+        self.dependencies.is_empty()
+            && self.use_statements.is_empty()
+            && self.tests.is_none()
+            && self.seed.is_none()
+            && self.strategy.is_none()
+            && self.function_tested_prefix.is_none()
+    }
+}
+
+/// Merges `defaults`, a `[defaults]` table, into `contract`, the
+/// table of a contract (see `Defaults`).
+fn merge_defaults(defaults: &toml::Table, contract: &mut toml::Table) {
+    for (key, value) in defaults {
+        match (key.as_str(), value, contract.get_mut(key)) {
+            ("dependencies", toml::Value::Table(dependencies), Some(toml::Value::Table(own))) => {
+                for (name, spec) in dependencies {
+                    own.entry(name).or_insert(spec.clone());
+                }
+            }
+            ("use_statements", toml::Value::Array(uses), Some(toml::Value::Array(own))) => {
+                let own = std::mem::take(own);
+                let mut merged = uses.clone();
+                merged.extend(own.into_iter().filter(|u| !uses.contains(u)));
+                contract.insert(key.clone(), toml::Value::Array(merged));
+            }
+            ("function_tested_prefix", toml::Value::String(prefix), _) => {
+                if let Some(toml::Value::String(function)) = contract.get_mut("function_tested") {
+                    *function = match function.trim_start() {
+                        qualified if qualified.starts_with('<') => qualified.to_string(),
+                        function => match function.strip_prefix("::") {
+                            Some(absolute) => absolute.to_string(),
+                            None => format!("{prefix}::{function}"),
+                        },
+                    };
+                }
+            }
+            (_, _, None) => {
+                contract.insert(key.clone(), value.clone());
+            }
+            _ => (),
+        }
+    }
+}
+
+/// A problem found in a contracts file.
+#[derive(Clone, Debug)]
+pub struct Problem {
+    /// Where the problem is in the contracts file, if known.
+    pub span: Option<Span>,
+    /// The index of the contract at fault, if any.
+    pub contract: Option<usize>,
+    /// The field of the contract at fault, if known (e.g.
+    /// `precondition` or `inputs[0].typ`).
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl Problem {
+    pub(crate) fn new(
+        contract: Option<usize>,
+        field: Option<String>,
+        message: impl ToString,
+    ) -> Self {
+        Self {
+            span: None,
+            contract,
+            field,
+            message: message.to_string(),
+        }
+    }
+
+    pub(crate) fn with_span(mut self, span: Option<&Span>) -> Self {
+        self.span = span.filter(|span| !span.is_dummy()).cloned();
+        self
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(span) = &self.span {
+            write!(f, "{span}: ")?;
+        }
+        match (&self.contract, &self.field) {
+            (Some(nth), Some(field)) => write!(f, "contract #{nth}, field `{field}`: ")?,
+            (Some(nth), None) => write!(f, "contract #{nth}: ")?,
+            _ => (),
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Tries to parse the string at `field` in `table` as a `T`.
+fn probe<T: serde_via::SerdeVia<Repr = String>>(
+    table: &toml::Table,
+    field: &str,
+) -> Option<Result<(), String>> {
+    let value = table.get(field)?.as_str()?;
+    Some(
+        T::from_repr(value.to_string())
+            .map(|_| ())
+            .map_err(|err| err.to_string()),
+    )
+}
+
+/// Finds the fields of a contract which are not valid Rust syntax.
+fn syntax_errors(table: &toml::Table) -> Vec<(String, String)> {
+    let mut errors = vec![];
+    let mut report = |field: String, result: Option<Result<(), String>>| {
+        if let Some(Err(err)) = result {
+            errors.push((field, err));
+        }
+    };
+    report(
+        "precondition".into(),
+        probe::<syn::Expr>(table, "precondition"),
+    );
+    report(
+        "postcondition".into(),
+        probe::<syn::Expr>(table, "postcondition"),
+    );
+    report(
+        "function_tested".into(),
+        probe::<syn::ExprPath>(table, "function_tested"),
+    );
+    if let Some(toml::Value::Array(use_statements)) = table.get("use_statements") {
+        for (i, use_statement) in use_statements.iter().enumerate() {
+            let table = toml::Table::from_iter([("use".to_string(), use_statement.clone())]);
+            report(
+                format!("use_statements[{i}]"),
+                probe::<syn::UseTree>(&table, "use"),
+            );
+        }
+    }
+    if let Some(toml::Value::Array(inputs)) = table.get("inputs") {
+        for (i, input) in inputs.iter().enumerate() {
+            let Some(input) = input.as_table() else {
+                continue;
+            };
+            report(format!("inputs[{i}].typ"), probe::<syn::Type>(input, "typ"));
+            report(
+                format!("inputs[{i}].bounds"),
+                probe::<syn::WhereClause>(input, "bounds"),
+            );
+        }
+    }
+    errors
+}
+
+/// The spans of the contracts of a file, and of their fields.
+#[derive(Deserialize)]
+struct SpannedFile {
+    #[serde(default)]
+    contracts: Vec<toml::Spanned<HashMap<String, toml::Spanned<toml::Value>>>>,
+}
+
+/// The span of a contract, and the spans of its fields.
+type ContractSpans = (Span, HashMap<String, Span>);
+
+/// Computes the spans of the contracts of `source`, the contents of
+/// `file`.
+fn spans(source: &str, file: Option<&Path>) -> Vec<ContractSpans> {
+    let Ok(spanned) = toml::from_str::<SpannedFile>(source) else {
+        return vec![];
+    };
+    let span = |range| Span::from_range(source, file.map(Path::to_path_buf), range);
+    spanned
+        .contracts
+        .iter()
+        .map(|contract| {
+            let fields = contract
+                .get_ref()
+                .iter()
+                .map(|(field, value)| (field.clone(), span(value.span())))
+                .collect();
+            (span(contract.span()), fields)
+        })
+        .collect()
+}
+
+/// Parses a contracts file, whose contents is `source`. Each contract
+/// is parsed on its own, so that every faulty contract is
+/// reported. Contracts carry the spans of their definition in `file`.
+pub fn parse(source: &str, file: Option<&Path>) -> Result<ContractsFile, Vec<Problem>> {
+    let table: toml::Table = toml::from_str(source).map_err(|err| {
+        let span = err
+            .span()
+            .map(|range| Span::from_range(source, file.map(Path::to_path_buf), range));
+        vec![Problem::new(None, None, err.message()).with_span(span.as_ref())]
+    })?;
+    let include = match table.get("include") {
+        None => vec![],
+        Some(include) => include.clone().try_into::<Vec<PathBuf>>().map_err(|_| {
+            vec![Problem::new(
+                None,
+                Some("include".into()),
+                "expected an array of paths",
+            )]
+        })?,
+    };
+    let defaults = match table.get("defaults") {
+        None => toml::Table::new(),
+        Some(toml::Value::Table(defaults)) => defaults.clone(),
+        Some(_) => {
+            return Err(vec![Problem::new(
+                None,
+                Some("defaults".into()),
+                "expected a table",
+            )])
+        }
+    };
+    let typed_defaults: Defaults =
+        toml::Value::Table(defaults.clone())
+            .try_into()
+            .map_err(|err: toml::de::Error| {
+                vec![Problem::new(None, Some("defaults".into()), err.message())]
+            })?;
+    let contracts = match table.get("contracts") {
+        Some(toml::Value::Array(contracts)) => &contracts[..],
+        None if !include.is_empty() => &[],
+        _ => {
+            return Err(vec![Problem::new(
+                None,
+                None,
+                "expected an array of contracts `[[contracts]]`",
+            )])
+        }
+    };
+    let spans = spans(source, file);
+    let mut problems = vec![];
+    let mut result = vec![];
+    for (nth, value) in contracts.iter().enumerate() {
+        let (span, field_spans) = spans
+            .get(nth)
+            .cloned()
+            .unwrap_or_else(|| (Span::dummy(), HashMap::new()));
+        let mut value = value.clone();
+        if let toml::Value::Table(contract) = &mut value {
+            merge_defaults(&defaults, contract);
+        }
+        match value.clone().try_into::<Contract>() {
+            Ok(mut contract) => {
+                contract.span = span;
+                contract.field_spans = field_spans;
+                result.push(contract)
+            }
+            Err(err) => {
+                let syntax_errors = value.as_table().map(syntax_errors).unwrap_or_default();
+                if syntax_errors.is_empty() {
+                    problems
+                        .push(Problem::new(Some(nth), None, err.message()).with_span(Some(&span)));
+                }
+                for (field, err) in syntax_errors {
+                    // `inputs[0].typ` is located at `inputs`
+                    let field_span = field_spans
+                        .get(field.split(['[', '.']).next().unwrap())
+                        .unwrap_or(&span);
+                    problems.push(
+                        Problem::new(Some(nth), Some(field), err).with_span(Some(field_span)),
+                    );
+                }
+            }
+        }
+    }
+    match problems.is_empty() {
+        true => Ok(ContractsFile {
+            include,
+            defaults: typed_defaults,
+            contracts: result,
+        }),
+        false => Err(problems),
+    }
+}
+
+/// Fills in the inputs, precondition and postcondition of the
+/// `completed` contracts, given with their index in `source`, the
+/// contents of a contracts file. The rest of the file (comments, key
+/// order, `[defaults]`...) is left untouched, and expressions and
+/// types are pretty-printed.
+pub fn complete(
+    source: &str,
+    completed: &[(usize, Contract)],
+) -> Result<String, toml_edit::TomlError> {
+    let mut document: toml_edit::DocumentMut = source.parse()?;
+    for (nth, contract) in completed {
+        let Some(table) = document.get_mut("contracts").and_then(|c| c.get_mut(*nth)) else {
+            continue;
+        };
+        let mut inputs = toml_edit::Array::new();
+        for input in &contract.inputs {
+            let mut table = toml_edit::InlineTable::new();
+            table.insert("name", input.name.as_str().into());
+            match &input.kind {
+                InputKind::Value { typ, aliases } => {
+                    table.insert("typ", pretty_type(typ).into());
+                    table.insert("aliases", aliases.iter().collect());
+                }
+                InputKind::Type { bounds } => {
+                    table.insert("bounds", pretty_where_clause(bounds).into());
+                }
+            }
+            let mut value = toml_edit::Value::InlineTable(table);
+            value.decor_mut().set_prefix("\n    ");
+            inputs.push_formatted(value);
+        }
+        inputs.set_trailing_comma(!inputs.is_empty());
+        inputs.set_trailing(if inputs.is_empty() { "" } else { "\n" });
+        table["inputs"] = toml_edit::value(inputs);
+        table["precondition"] = toml_string(pretty_expr(&contract.precondition));
+        table["postcondition"] = toml_string(pretty_expr(&contract.postcondition));
+    }
+    Ok(document.to_string())
+}
+
+/// A TOML string holding `text`, written as a multi-line literal
+/// string when `text` spans several lines.
+fn toml_string(text: String) -> toml_edit::Item {
+    if text.contains('\n') && !text.contains("'''") && !text.ends_with('\'') {
+        if let Ok(mut value) = format!("'''\n{text}'''").parse::<toml_edit::Value>() {
+            value.decor_mut().clear();
+            return toml_edit::value(value);
+        }
+    }
+    toml_edit::value(text)
+}
+
+/// Reads the contracts at `path`: either a contracts file, along with
+/// the files it includes, or a directory, in which every
+/// `*.contracts.toml` file is read recursively. The contracts
+/// declared in the sources of a crate (see `attributes::collect`)
+/// are read along with the contracts files of its directory. The
+/// relative paths of dependencies are resolved against the directory
+/// of the file declaring them.
+pub fn read(path: &Path) -> Result<Vec<Contract>, Vec<Problem>> {
+    let mut contracts = vec![];
+    let mut problems = vec![];
+    read_into(path, &mut HashSet::new(), &mut contracts, &mut problems);
+    match problems.is_empty() {
+        true => Ok(contracts),
+        false => Err(problems),
+    }
+}
+
+/// Reads the contracts at `path` (see `read`) into `contracts`, or
+/// reports problems. Files already `visited` are skipped, so that
+/// include cycles are harmless.
+fn read_into(
+    path: &Path,
+    visited: &mut HashSet<PathBuf>,
+    contracts: &mut Vec<Contract>,
+    problems: &mut Vec<Problem>,
+) {
+    let could_not_read = |err: std::io::Error| {
+        Problem::new(
+            None,
+            None,
+            format!("could not read {}: {err}", path.display()),
+        )
+    };
+    let canonical = match std::fs::canonicalize(path) {
+        Ok(canonical) => canonical,
+        Err(err) => return problems.push(could_not_read(err)),
+    };
+    if !visited.insert(canonical.clone()) {
+        return;
+    }
+    if canonical.is_dir() {
+        if canonical.join("Cargo.toml").is_file() {
+            match crate::attributes::collect(&canonical) {
+                Ok(collected) => contracts.extend(collected),
+                Err(collected) => problems.extend(collected),
+            }
+        }
+        match contracts_files(&canonical) {
+            Ok(files) => {
+                for file in files {
+                    read_into(&file, visited, contracts, problems);
+                }
+            }
+            Err(err) => problems.push(could_not_read(err)),
+        }
+        return;
+    }
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => return problems.push(could_not_read(err)),
+    };
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    match parse(&source, Some(path)) {
+        Ok(file) => {
+            contracts.extend(file.contracts.into_iter().map(|mut contract| {
+                contract.normalize_paths(base_dir);
+                contract
+            }));
+            for include in file.include {
+                read_into(&base_dir.join(include), visited, contracts, problems);
+            }
+        }
+        Err(file_problems) => {
+            // Problems without location are located at the file
+            let file_span = Span::from_range(&source, Some(path.to_path_buf()), 0..0);
+            problems.extend(file_problems.into_iter().map(|mut problem| {
+                problem.span.get_or_insert(file_span.clone());
+                problem
+            }))
+        }
+    }
+}
+
+/// Finds the `*.contracts.toml` files in `dir` and its
+/// subdirectories (skipping hidden directories and `target`), in a
+/// stable order.
+fn contracts_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut entries: Vec<_> = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    entries.sort();
+    for path in entries {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if path.is_dir() {
+            if !name.starts_with('.') && name != "target" {
+                files.extend(contracts_files(&path)?);
+            }
+        } else if name.ends_with(EXTENSION) {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Identifiers that may appear free in a contract.
+const KNOWN_IDENTS: [&str; 3] = ["eval", "drop", "self"];
+
+/// Finds the identifiers of the pre- and postcondition of `contract`
+/// that are bound neither by an input, nor by a `use` statement, nor
+/// by a pattern. Identifiers starting with an uppercase letter (types,
+/// constants or variants) are ignored, and so is every identifier
+/// when a `use` statement is a glob.
+pub fn free_idents(contract: &Contract) -> Vec<syn::Ident> {
+    fn imported(tree: &syn::UseTree, names: &mut HashSet<String>) -> bool {
+        match tree {
+            syn::UseTree::Path(path) => imported(&path.tree, names),
+            syn::UseTree::Name(name) => {
+                names.insert(name.ident.to_string());
+                true
+            }
+            syn::UseTree::Rename(rename) => {
+                names.insert(rename.rename.to_string());
+                true
+            }
+            syn::UseTree::Glob(_) => false,
+            syn::UseTree::Group(group) => group.items.iter().all(|tree| imported(tree, names)),
+        }
+    }
+    let mut bound: HashSet<String> = KNOWN_IDENTS.iter().map(|s| s.to_string()).collect();
+    for input in &contract.inputs {
+        bound.insert(input.name.clone());
+        if let InputKind::Value { aliases, .. } = &input.kind {
+            bound.extend(aliases.iter().cloned());
+        }
+    }
+    for tree in &contract.use_statements {
+        if !imported(tree, &mut bound) {
+            return vec![];
+        }
+    }
+    contract
+        .concrete_idents()
+        .into_iter()
+        .filter(|ident| {
+            let name = ident.to_string();
+            !bound.contains(&name) && !name.starts_with(|c: char| c.is_uppercase())
+        })
+        .sorted_by_key(|ident| ident.to_string())
+        .collect()
+}
+
+/// Builds the source of a function that type-checks the pre- and
+/// postcondition of `contract`. Value inputs are replaced by calls
+/// to `testify_input::<T>()`, so that every occurrence of an input is
+/// a fresh value (as it is once instantiated), and type inputs become
+/// generic parameters.
+fn type_check_fn(nth: usize, contract: &Contract) -> proc_macro2::TokenStream {
+    let mut contract = contract.clone();
+    let values: Vec<_> = contract
+        .inputs
+        .iter()
+        .filter_map(|input| match &input.kind {
+            InputKind::Value { typ, .. } => Some((input.name.clone(), typ.clone())),
+            InputKind::Type { .. } => None,
+        })
+        .collect();
+    for (name, typ) in values {
+        contract.instantiate_input(
+            &name,
+            crate::InputInstance::SimpleValue(parse_quote! {testify_input::<#typ>()}),
+        );
+    }
+    let (generics, predicates): (Vec<_>, Vec<_>) = contract
+        .inputs
+        .iter()
+        .filter_map(|input| match &input.kind {
+            InputKind::Type { bounds } => Some((
+                syn::Ident::new(&input.name, proc_macro2::Span::call_site()),
+                bounds.predicates.clone(),
+            )),
+            InputKind::Value { .. } => None,
+        })
+        .unzip();
+    let predicates = predicates.iter().flatten();
+    let ident = syn::Ident::new(&format!("contract_{nth}"), proc_macro2::Span::call_site());
+    let use_statements = &contract.use_statements;
+    let precondition = mark(nth, "precondition", &contract.precondition);
+    let postcondition = mark(nth, "postcondition", &contract.postcondition);
+    quote! {
+        fn #ident<#(#generics),*>() where #(#predicates,)* {
+            #(use #use_statements;)*
+            let _: bool = #precondition;
+            let _: bool = #postcondition;
+        }
+    }
+}
+
+/// Type-checks the pre- and postconditions of `contracts` (whose
+/// dependencies are compatible) in a single `cargo check` pass. The
+/// errors are reported on the contract (and when possible, the field
+/// of the contract) they originate from, where `indexes` maps the
+/// contracts to their index in the file.
+fn type_check(contracts: &[&Contract], indexes: &[usize]) -> Vec<Problem> {
+    let mut krate = Krate::new();
+    for contract in contracts {
+        krate.add_dependencies(&contract.dependencies);
+    }
+    let functions = contracts
+        .iter()
+        .enumerate()
+        .map(|(nth, contract)| type_check_fn(nth, contract));
+    krate.source(
+        &quote! {
+            #![allow(unused, unreachable_code, non_snake_case)]
+            fn testify_input<T>() -> T {
+                unimplemented!()
+            }
+            fn eval<T>(value: T) -> T {
+                value
+            }
+            #(#functions)*
+            fn main() {}
+        }
+        .to_string(),
+    );
+
+    // The lines spanned by each function, once formatted
+    let source = std::fs::read_to_string(krate.path().join("main.rs")).unwrap();
+    let file = syn::parse_file(&source).unwrap();
+    let lines: Vec<(usize, std::ops::RangeInclusive<usize>)> = file
+        .items
+        .iter()
+        .filter_map(|item| {
+            use syn::spanned::Spanned;
+            let syn::Item::Fn(function) = item else {
+                return None;
+            };
+            let nth = function.sig.ident.to_string();
+            let nth: usize = nth.strip_prefix("contract_")?.parse().ok()?;
+            Some((nth, item.span().start().line..=item.span().end().line))
+        })
+        .collect();
+
+    let diagnostics = match krate.check() {
+        Ok(diagnostics) => diagnostics,
+        Err(err) => {
+            return vec![Problem::new(
+                None,
+                None,
+                format!("could not run `cargo check`: {err}"),
+            )]
+        }
+    };
+    locate_all(&diagnostics, &source, contracts)
+        .into_iter()
+        .map(|(diagnostic, origin)| {
+            if let Some(origin) = origin {
+                let contract = contracts[origin.contract];
+                let span = origin
+                    .span(contract)
+                    .or_else(|| contract.field_spans.get(&origin.field).cloned())
+                    .unwrap_or(contract.span.clone());
+                return Problem::new(
+                    Some(indexes[origin.contract]),
+                    Some(origin.field),
+                    &diagnostic.message,
+                )
+                .with_span(Some(&span));
+            }
+            let contract = diagnostic
+                .spans
+                .iter()
+                .find(|span| span.is_primary)
+                .and_then(|span| {
+                    lines
+                        .iter()
+                        .find(|(_, lines)| lines.contains(&span.line_start))
+                })
+                .map(|(nth, _)| *nth);
+            Problem::new(contract.map(|nth| indexes[nth]), None, &diagnostic.message)
+                .with_span(contract.map(|nth| &contracts[nth].span))
+        })
+        .collect()
+}
+
+/// Checks contracts: flags free identifiers, and type-checks inputs,
+/// preconditions and postconditions.
+pub fn check(contracts: &[Contract]) -> Vec<Problem> {
+    let mut problems = vec![];
+    for (nth, contract) in contracts.iter().enumerate() {
+        if let Some(Err(err)) = contract.function_tested.as_ref().map(TestedFunction::new) {
+            problems.push(
+                Problem::new(Some(nth), Some("function_tested".into()), err)
+                    .with_span(Some(&contract.span)),
+            );
+        }
+        for ident in free_idents(contract) {
+            problems.push(
+                Problem::new(
+                    Some(nth),
+                    None,
+                    format!("`{ident}` is neither an input nor imported by a `use` statement"),
+                )
+                .with_span(Some(&contract.span)),
+            );
+        }
+    }
+    for group in group_by_dependencies(contracts) {
+        let pool: Vec<_> = group.iter().map(|i| &contracts[*i]).collect();
+        problems.extend(type_check(&pool, &group));
+    }
+    problems
+}
+
+#[test]
+fn free_identifiers() {
+    let contract: Contract = toml::from_str(
+        r#"
+        description = ""
+        precondition = "x.len() > n && v.iter().all(|y| *y > 0)"
+        postcondition = "{ let z = f(x); z == Some(sum(w)) }"
+        use_statements = ["krate::{f, g as sum}"]
+        function_tested = "krate::f"
+        [[inputs]]
+        name = "x"
+        typ = "Vec<u8>"
+        aliases = ["v"]
+        "#,
+    )
+    .unwrap();
+    let free: Vec<_> = free_idents(&contract)
+        .iter()
+        .map(|ident| ident.to_string())
+        .collect();
+    assert_eq!(free, ["n", "w"]);
+}
+
+#[test]
+fn free_identifiers_ignore_types() {
+    let contract: Contract = toml::from_str(
+        r#"
+        description = ""
+        precondition = "(x as u32) < u8::MAX as u32 && i64::from(x) > 0"
+        postcondition = "Vec::<u8>::new().len() <= f::<u16>(x) as usize + n"
+        use_statements = ["krate::f"]
+        function_tested = "krate::f"
+        [[inputs]]
+        name = "x"
+        typ = "u8"
+        aliases = []
+        "#,
+    )
+    .unwrap();
+    let free: Vec<_> = free_idents(&contract)
+        .iter()
+        .map(|ident| ident.to_string())
+        .collect();
+    assert_eq!(free, ["n"]);
+}
+
+#[test]
+fn contract_spans() {
+    let source = r#"
+[[contracts]]
+description = ""
+function_tested = "krate::f"
+precondition = "true"
+
+[[contracts]]
+description = ""
+function_tested = "krate::f"
+postcondition = "true &&"
+"#;
+    let file = Path::new("contracts.toml");
+    let contracts = parse(&source.replace("true &&", "true"), Some(file))
+        .unwrap()
+        .contracts;
+    assert_eq!(contracts[0].span.to_string(), "contracts.toml:2:1");
+    assert_eq!(
+        contracts[1].field_spans["postcondition"].to_string(),
+        "contracts.toml:10:17"
+    );
+    let problems = parse(source, Some(file)).unwrap_err();
+    assert_eq!(problems[0].span.as_ref().unwrap().start.line, 10);
+}
+
+#[test]
+fn defaults() {
+    let source = r#"
+[defaults]
+function_tested_prefix = "krate::m"
+use_statements = ["krate::A"]
+tests = 10
+dependencies.krate = { path = "krate" }
+
+[[contracts]]
+description = ""
+function_tested = "f"
+use_statements = ["krate::B"]
+
+[[contracts]]
+description = ""
+function_tested = "::other::g"
+tests = 3
+dependencies.krate = { path = "elsewhere" }
+dependencies.other = { path = "other" }
+"#;
+    let contracts = parse(source, None).unwrap().contracts;
+    let function = |nth: usize| {
+        let function = contracts[nth].function_tested.as_ref().unwrap();
+        function.to_token_stream().to_string()
+    };
+    assert_eq!(function(0), "krate :: m :: f");
+    assert_eq!(function(1), "other :: g");
+    assert_eq!(contracts[0].use_statements.len(), 2);
+    assert_eq!((contracts[0].tests, contracts[1].tests), (10, 3));
+    assert_eq!(contracts[0].dependencies.len(), 1);
+    assert_eq!(contracts[1].dependencies.len(), 2);
+    assert_eq!(
+        contracts[1].dependencies["krate"].0["path"].as_str(),
+        Some("elsewhere")
+    );
+}
+
+#[test]
+fn complete_in_place() {
+    let source = r#"# Contracts of `krate`
+[defaults]
+function_tested_prefix = "krate"
+
+[[contracts]]
+description = "kept" # as is
+function_tested = "f"
+precondition = "x > 0"
+postcondition = "x.up() > x"
+inputs = [{ name = "x", typ = "u8", aliases = [] }]
+
+[[contracts]]
+description = "completed"
+function_tested = "g"
+"#;
+    let mut contract = parse(source, None).unwrap().contracts.remove(0);
+    contract.precondition = parse_quote! {x.len() > 0};
+    contract.postcondition = parse_quote! {
+        x.iter().map(|y| y.saturating_add(1)).filter(|y| *y > 10).all(|y| y < 200) || x.is_empty()
+    };
+    contract.inputs.push(Input {
+        name: "T".into(),
+        kind: InputKind::Type {
+            bounds: parse_quote! {where T: Clone + PartialEq<u8>},
+        },
+    });
+    let completed = complete(source, &[(1, contract.clone())]).unwrap();
+    let (kept, rest) = completed.split_once("description = \"completed\"").unwrap();
+    assert!(source.starts_with(kept));
+    assert_eq!(
+        rest,
+        r#"
+function_tested = "g"
+inputs = [
+    { name = "x", typ = "u8", aliases = [] },
+    { name = "T", bounds = "where T: Clone + PartialEq<u8>" },
+]
+precondition = "x.len() > 0"
+postcondition = '''
+x.iter().map(|y| y.saturating_add(1)).filter(|y| *y > 10).all(|y| y < 200)
+|| x.is_empty()'''
+"#
+    );
+    let reread = parse(&completed, None).unwrap().contracts.remove(1);
+    assert_eq!(
+        reread.postcondition.to_token_stream().to_string(),
+        contract.postcondition.to_token_stream().to_string()
+    );
+}
+
+#[test]
+fn read_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    let write = |path: &str, contents: &str| {
+        let path = dir.path().join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    };
+    let contract = |description: &str, path: &str| {
+        format!(
+            "[[contracts]]\ndescription = \"{description}\"\nfunction_tested = \"krate::f\"\ndependencies.krate = {{ path = \"{path}\" }}\n"
+        )
+    };
+    // Includes are relative to the including file, and may be cyclic
+    write(
+        "a.contracts.toml",
+        &format!(
+            "include = [\"shared/common.toml\"]\n{}",
+            contract("a", "../krate")
+        ),
+    );
+    write(
+        "shared/common.toml",
+        &format!(
+            "include = [\"../a.contracts.toml\"]\n{}",
+            contract("common", "./../../krate")
+        ),
+    );
+    write("sub/b.contracts.toml", &contract("b", "krate"));
+    // Hidden directories, `target` and other files are skipped
+    write(".hidden/c.contracts.toml", &contract("hidden", "krate"));
+    write("target/d.contracts.toml", &contract("target", "krate"));
+    write("notes.toml", &contract("notes", "krate"));
+
+    let contracts = read(dir.path()).unwrap();
+    let descriptions: Vec<_> = contracts
+        .iter()
+        .map(|contract| contract.description.as_str())
+        .collect();
+    assert_eq!(descriptions, ["a", "common", "b"]);
+    // `..` is resolved, so that the same crate is the same path
+    // across files
+    let root = std::fs::canonicalize(dir.path()).unwrap();
+    let path = |nth: usize| {
+        let path = &contracts[nth].dependencies["krate"].0["path"];
+        PathBuf::from(path.as_str().unwrap())
+    };
+    assert_eq!(path(0), root.parent().unwrap().join("krate"));
+    assert_eq!(path(1), path(0));
+    assert_eq!(path(2), root.join("sub/krate"));
+
+    // Included files are resolved relative to the including file
+    write("other/main.toml", "include = [\"missing.toml\"]\n");
+    let problems = read(&dir.path().join("other/main.toml")).unwrap_err();
+    assert!(problems[0]
+        .to_string()
+        .contains(&dir.path().join("other/missing.toml").display().to_string()));
+}
@@ -1,12 +1,14 @@
+use crate::coverage::{BadCoverageReport, CoverageBackend};
 use crate::prelude::*;
 use crate::*;
 use colored::Colorize;
+use std::sync::Arc;
 
 /// Make sure a binary is in PATH.
-fn require_binary(bin: &str) {
-    if which::which(bin).is_err() {
-        println!("{}", format!("Could not find binary {}", bin.bold()).red());
-        std::process::exit(1);
+fn require_binary(bin: &str) -> Result<(), Error> {
+    match which::which(bin) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(Error::MissingTool(bin.to_string())),
     }
 }
 
@@ -18,9 +20,313 @@ pub fn setup_tracing() {
     tracing::subscriber::set_global_default(subscriber).unwrap();
 }
 
+/// Options for the default "driver".
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Check for coverage of the functions tested by the contracts.
+    pub coverage: bool,
+    /// The tool used to compute coverage.
+    pub coverage_backend: Arc<dyn CoverageBackend>,
+    /// Fail the run when the line coverage (in percent) of a tested
+    /// function is below this threshold. Contracts can override it
+    /// with `min_coverage`.
+    pub min_coverage: Option<f64>,
+    /// Generate inputs guided by coverage, for at most this many
+    /// rounds per tested function.
+    pub coverage_guided: Option<usize>,
+    /// Write the coverage reports as an lcov tracefile at this path.
+    pub lcov: Option<PathBuf>,
+    /// Write the coverage reports as a static HTML page at this path.
+    pub html: Option<PathBuf>,
+    /// Run mutation testing on the functions tested by the contracts,
+    /// giving up on a mutant after this duration.
+    pub mutation: Option<std::time::Duration>,
+    /// The number of pools processed concurrently.
+    pub jobs: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            coverage: false,
+            coverage_backend: Arc::new(coverage::Tarpaulin),
+            min_coverage: None,
+            coverage_guided: None,
+            lcov: None,
+            html: None,
+            mutation: None,
+            jobs: 1,
+        }
+    }
+}
+
+/// Selects contracts by name, tag or tested function. A contract is
+/// selected when it satisfies every criterion given.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /// A glob (with `*` and `?` wildcards) the name of the contract
+    /// should match. Contracts without a name are matched by
+    /// description.
+    pub name: Option<String>,
+    /// Select the contracts having at least one of these tags.
+    pub tags: Vec<String>,
+    /// Select the contracts testing one of these functions. Paths
+    /// that are not supported (see `TestedFunction::new`) match no
+    /// contract.
+    pub functions: Vec<syn::ExprPath>,
+}
+
+impl Filter {
+    /// Whether `contract` is selected by the filter.
+    pub fn matches(&self, contract: &Contract) -> bool {
+        let name = contract.name.as_ref().unwrap_or(&contract.description);
+        self.name
+            .as_ref()
+            .map_or(true, |glob| glob_matches(glob, name))
+            && (self.tags.is_empty() || self.tags.iter().any(|tag| contract.tags.contains(tag)))
+            && (self.functions.is_empty()
+                || contract.function_tested().is_some_and(|tested| {
+                    self.functions
+                        .iter()
+                        .any(|function| TestedFunction::new(function).is_ok_and(|f| f == tested))
+                }))
+    }
+
+    /// Keeps the contracts selected by the filter.
+    pub fn apply(&self, contracts: Vec<Contract>) -> Vec<Contract> {
+        contracts
+            .into_iter()
+            .filter(|contract| self.matches(contract))
+            .collect()
+    }
+}
+
+/// Whether `text` matches `glob`, where `*` stands for any sequence
+/// of characters, and `?` for any character.
+fn glob_matches(glob: &str, text: &str) -> bool {
+    let pattern = regex::escape(glob).replace(r"\*", ".*").replace(r"\?", ".");
+    regex::Regex::new(&format!("^{pattern}$")).is_ok_and(|regex| regex.is_match(text))
+}
+
+/// Prints the contracts, grouped by the pool they are assigned to,
+/// along with the function they test.
+pub fn list(contracts: &[Contract]) {
+    for (nth, pool) in pool::group_by_dependencies(contracts).iter().enumerate() {
+        println!("{}", format!("Pool {}", nth + 1).bold());
+        for contract in pool.iter().map(|i| &contracts[*i]) {
+            let name = match &contract.name {
+                Some(name) => format!("{} ({})", name.bold(), contract.description),
+                None => contract.description.clone(),
+            };
+            let tested = match &contract.function_tested {
+                Some(function) => format!("`{}`", function.to_token_stream()).replace(' ', ""),
+                None => "no tested function".italic().to_string(),
+            };
+            let tags = contract.tags.iter().map(|tag| format!(" #{tag}")).join("");
+            println!(" - {name} → {tested}{}", tags.dimmed());
+        }
+    }
+}
+
+/// Fails if a contract tests a function whose path is not supported
+/// (see `TestedFunction::new`).
+fn check_functions_tested(contracts: &[Contract]) -> Result<(), Error> {
+    contracts
+        .iter()
+        .filter_map(|contract| contract.function_tested.as_ref())
+        .try_for_each(|path| TestedFunction::new(path).map(drop))
+}
+
 /// Run the default "driver" for a list of contracts.
-pub fn run(contracts: Vec<Contract>, outfile: impl AsRef<Path>, coverage: bool) {
-    require_binary("cargo-tarpaulin");
+pub fn run(
+    contracts: Vec<Contract>,
+    outfile: impl AsRef<Path>,
+    coverage: bool,
+) -> Result<(), Error> {
+    run_with_options(
+        contracts,
+        outfile,
+        &Options {
+            coverage,
+            ..Options::default()
+        },
+    )
+}
+
+/// Print a summary of the public functions of the crates `krates`
+/// (by default, the crates tested by `contracts`), as a table or as
+/// JSON.
+pub fn coverage_summary(
+    contracts: Vec<Contract>,
+    krates: &[String],
+    backend: &dyn CoverageBackend,
+    json: bool,
+) -> Result<(), Error> {
+    check_functions_tested(&contracts)?;
+    require_binary(backend.required_binary())?;
+    let summary = summary::coverage_summary(contracts, krates, backend)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&summary).unwrap());
+    } else {
+        print!("{summary}");
+    }
+    Ok(())
+}
+
+/// Checks the contracts at `path` (see `contracts_file::read`) without generating
+/// tests, printing the problems found. Returns whether the contracts
+/// are free of problems.
+pub fn check(path: &Path) -> bool {
+    let problems = match contracts_file::read(path) {
+        Ok(contracts) => {
+            let problems = contracts_file::check(&contracts);
+            if problems.is_empty() {
+                println!(
+                    "{}",
+                    format!("✓ {} contracts checked, no problem found.", contracts.len()).green()
+                );
+                return true;
+            }
+            problems
+        }
+        Err(problems) => problems,
+    };
+    println!(
+        "{}",
+        format!("Found {} problem(s):", problems.len()).red().bold()
+    );
+    for problem in problems {
+        println!(" - {problem}");
+    }
+    false
+}
+
+/// Finds the reports whose coverage is below the applicable
+/// threshold, returning them along with that threshold.
+fn coverage_below_threshold(
+    reports: &[BadCoverageReport],
+    default_threshold: Option<f64>,
+) -> Vec<(&BadCoverageReport, f64)> {
+    reports
+        .iter()
+        .flat_map(|report| {
+            let threshold = report.min_coverage.or(default_threshold)?;
+            (report.coverage_percent() < threshold).then_some((report, threshold))
+        })
+        .collect()
+}
+
+/// The result of processing a pool.
+struct PoolOutput {
+    /// The assertions generated for the pool, as source code:
+    /// token streams cannot be sent across threads.
+    assertions: Vec<String>,
+    coverage_reports: Vec<BadCoverageReport>,
+    mutation_reports: Vec<crate::mutation::MutationReport>,
+}
+
+/// Runs the pipeline on the `nth` pool.
+fn process_pool(
+    nth: usize,
+    pool: pool::ContractPool<pool::GenericContracts>,
+    options: &Options,
+) -> Result<PoolOutput, Error> {
+    let mut coverage_reports = vec![];
+    let mut mutation_reports = vec![];
+    let mut step: usize = 0;
+    macro_rules! log {
+        ($format:literal $($r:tt)*) => {
+            println!(concat!(" {} ", $format), ["①", "②", "③", "④", "⑤", "⑥"][step] $($r)*);
+            #[allow(unused)]
+            {
+                step += 1;
+            }
+        };
+    }
+    log!("Instantiating types (pool {})...", nth + 1);
+    let pool = pool.instantiate_types()?;
+    log!("Instantiating values (pool {})...", nth + 1);
+    let mut pool = match options.coverage_guided {
+        Some(budget) => {
+            pool.instantiate_values_guided(options.coverage_backend.as_ref(), budget)?
+        }
+        None => pool.instantiate_values()?,
+    };
+    log!("Computing eval nodes (pool {})...", nth + 1);
+    pool.compute_eval_nodes()?;
+    if options.coverage {
+        log!("Computing coverage (pool {})...", nth + 1);
+        coverage_reports.extend(pool.compute_coverage(options.coverage_backend.as_ref())?);
+    }
+    if let Some(timeout) = options.mutation {
+        log!("Mutation testing (pool {})...", nth + 1);
+        mutation_reports.extend(pool.mutation_testing(timeout)?);
+    }
+    log!("Done! Saving assertions (pool {}).", nth + 1);
+
+    Ok(PoolOutput {
+        assertions: pool
+            .contracts()
+            .iter()
+            .map(|contract| contract.as_assertion().to_string())
+            .collect(),
+        coverage_reports,
+        mutation_reports,
+    })
+}
+
+/// Processes the pools, running up to `jobs` pools concurrently.
+/// Fails with the error of the first failing pool.
+fn process_pools(
+    pools: Vec<pool::ContractPool<pool::GenericContracts>>,
+    options: &Options,
+) -> Result<Vec<PoolOutput>, Error> {
+    if options.jobs <= 1 {
+        return pools
+            .into_iter()
+            .enumerate()
+            .map(|(nth, pool)| process_pool(nth, pool, options))
+            .collect();
+    }
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    let pools: Vec<String> = pools.iter().map(|pool| pool.to_json()).collect();
+    let next = AtomicUsize::new(0);
+    let outputs: Mutex<Vec<Option<Result<PoolOutput, Error>>>> =
+        Mutex::new(pools.iter().map(|_| None).collect());
+    std::thread::scope(|scope| {
+        for job in 0..options.jobs.min(pools.len()) {
+            let (pools, next, outputs) = (&pools, &next, &outputs);
+            scope.spawn(move || {
+                crate::krate::workspace::set_job(job);
+                loop {
+                    let nth = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(pool) = pools.get(nth) else { break };
+                    let output = process_pool(nth, pool::ContractPool::from_json(pool), options);
+                    outputs.lock().unwrap()[nth] = Some(output);
+                }
+            });
+        }
+    });
+    outputs
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(Option::unwrap)
+        .collect()
+}
+
+/// Run the default "driver" for a list of contracts, with custom options.
+pub fn run_with_options(
+    contracts: Vec<Contract>,
+    outfile: impl AsRef<Path>,
+    options: &Options,
+) -> Result<(), Error> {
+    check_functions_tested(&contracts)?;
+    let coverage = options.coverage;
+    if coverage || options.coverage_guided.is_some() {
+        require_binary(options.coverage_backend.required_binary())?;
+    }
 
     let contracts_len = contracts.len();
 
@@ -34,38 +340,18 @@ pub fn run(contracts: Vec<Contract>, outfile: impl AsRef<Path>, coverage: bool)
     use std::fs;
     let _ = fs::remove_file(&outfile);
 
-    let mut resulting_assertions = vec![];
+    let mut resulting_assertions: Vec<proc_macro2::TokenStream> = vec![];
     let mut coverage_reports = vec![];
-
-    for (nth, pool) in pools.into_iter().enumerate() {
-        let mut step: usize = 0;
-        macro_rules! log {
-            ($format:literal $($r:tt)*) => {
-                println!(concat!(" {} ", $format), ["①", "②", "③", "④", "⑤"][step] $($r)*);
-                #[allow(unused)]
-                {
-                    step += 1;
-                }
-            };
-        }
-        log!("Instantiating types (pool {})...", nth + 1);
-        let pool = pool.instantiate_types();
-        log!("Instantiating values (pool {})...", nth + 1);
-        let mut pool = pool.instantiate_values();
-        log!("Computing eval nodes (pool {})...", nth + 1);
-        pool.compute_eval_nodes();
-        if coverage {
-            log!("Computing coverage (pool {})...", nth + 1);
-            coverage_reports.extend(pool.compute_coverage());
-        }
-        log!("Done! Saving assertions (pool {}).", nth + 1);
-
-        let assertions = pool
-            .contracts()
-            .iter()
-            .map(|contract| contract.as_assertion());
-
-        resulting_assertions.extend(assertions);
+    let mut mutation_reports = vec![];
+    for output in process_pools(pools, options)? {
+        resulting_assertions.extend(
+            output
+                .assertions
+                .iter()
+                .map(|assertion| assertion.parse::<proc_macro2::TokenStream>().unwrap()),
+        );
+        coverage_reports.extend(output.coverage_reports);
+        mutation_reports.extend(output.mutation_reports);
     }
 
     fs::write(
@@ -75,6 +361,61 @@ pub fn run(contracts: Vec<Contract>, outfile: impl AsRef<Path>, coverage: bool)
                 #(#resulting_assertions)*
             }
         }),
-    )
-    .expect("Unable to write file");
+    )?;
+
+    if options.mutation.is_some() {
+        let survivors = mutation_reports
+            .iter()
+            .map(|report| report.survivors().count())
+            .sum::<usize>();
+        let viable = mutation_reports
+            .iter()
+            .map(|report| report.viable())
+            .sum::<usize>();
+        let summary = format!(
+            "Mutation testing: {survivors} out of {viable} mutants survived in {} functions.",
+            mutation_reports.len()
+        );
+        match survivors {
+            0 => println!("{}", summary.green()),
+            _ => println!("{}", summary.yellow()),
+        }
+    }
+
+    if let Some(path) = &options.lcov {
+        // The formatted sources the tracefile refers to are saved
+        // next to it
+        let sources = std::path::absolute(path.with_extension("sources"))?;
+        fs::write(path, export::lcov(&coverage_reports, &sources)?)?;
+    }
+    if let Some(path) = &options.html {
+        fs::write(path, export::html(&coverage_reports))?;
+    }
+
+    let failing = coverage_below_threshold(&coverage_reports, options.min_coverage);
+    if !failing.is_empty() {
+        return Err(Error::CoverageBelowThreshold(
+            failing
+                .into_iter()
+                .map(|(report, threshold)| {
+                    format!(
+                        "`{}`: {:.1}% < {}%",
+                        report.item_path,
+                        report.coverage_percent(),
+                        threshold
+                    )
+                })
+                .collect(),
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn glob() {
+    assert!(glob_matches("parse_*", "parse_u8"));
+    assert!(glob_matches("a?c", "abc"));
+    assert!(!glob_matches("a?c", "abbc"));
+    assert!(!glob_matches("parse", "parse_u8"));
+    assert!(glob_matches("(x)*", "(x) + 1"));
 }
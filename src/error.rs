@@ -0,0 +1,110 @@
+//! This module defines `Error`, the errors that may occur when
+//! running the pipeline on contracts.
+use crate::prelude::*;
+use std::fmt;
+
+/// The contract an error is about. Contracts hold `syn` values,
+/// which cannot be sent to another thread: errors refer to contracts
+/// by their description and their span instead.
+#[derive(Clone, Debug)]
+pub struct ContractRef {
+    pub description: String,
+    pub span: Span,
+}
+
+impl From<&Contract> for ContractRef {
+    fn from(contract: &Contract) -> Self {
+        Self {
+            description: contract.description.clone(),
+            span: contract.span.clone(),
+        }
+    }
+}
+
+impl fmt::Display for ContractRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: contract `{}`", self.span, self.description)
+    }
+}
+
+/// Formats the contracts an error is about, one per line.
+fn for_contracts(contracts: &[ContractRef]) -> String {
+    match contracts {
+        [] => String::new(),
+        contracts => format!(
+            " for the following contracts:{}",
+            contracts
+                .iter()
+                .map(|contract| format!("\n - {contract}"))
+                .collect::<String>()
+        ),
+    }
+}
+
+/// The ways running the pipeline on contracts can fail.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("hax failed{}:\n\n```\n{stderr}\n```", for_contracts(contracts))]
+    Hax {
+        contracts: Vec<ContractRef>,
+        stderr: String,
+    },
+    #[error(
+        "compilation failed{}:\n\n```\n{stderr}\n```",
+        for_contracts(contracts)
+    )]
+    Compile {
+        contracts: Vec<ContractRef>,
+        stderr: String,
+    },
+    #[error(
+        "the evaluation program crashed{}:\n\n```\n{message}\n```",
+        for_contracts(contracts)
+    )]
+    Crashed {
+        contracts: Vec<ContractRef>,
+        message: String,
+    },
+    #[error("{contract}: the precondition panicked on inputs ({inputs})")]
+    PreconditionPanic {
+        contract: ContractRef,
+        inputs: String,
+    },
+    #[error("{contract}: an `eval` node panicked: {message}")]
+    Eval {
+        contract: ContractRef,
+        message: String,
+    },
+    #[error("{contract}: the precondition server failed: {message}")]
+    Server {
+        contract: ContractRef,
+        message: String,
+    },
+    #[error("{contract}: generic contracts are not supported yet")]
+    Generic { contract: ContractRef },
+    #[error("could not find crate `{name}`: please make sure your contract declares a dependency on that crate. The dependencies currently available are: {}", available.join(", "))]
+    MissingCrate {
+        name: String,
+        available: Vec<String>,
+    },
+    #[error("`{function}` is not supported as a tested function: {reason}")]
+    UnsupportedFunction { function: String, reason: String },
+    #[error("could not find `{function}` in crate `{krate}`")]
+    MissingFunction { function: String, krate: String },
+    #[error(
+        "crate `{0}` has no library: only the public functions of a library can be summarized"
+    )]
+    MissingLibrary(String),
+    #[error("could not find binary `{0}`")]
+    MissingTool(String),
+    #[error(
+        "coverage is below the threshold for {} function(s):{}",
+        .0.len(),
+        .0.iter().map(|function| format!("\n - {function}")).collect::<String>()
+    )]
+    CoverageBelowThreshold(Vec<String>),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -0,0 +1,357 @@
+//! This module provides a tool-agnostic representation of coverage
+//! reports, and the `CoverageBackend` trait implemented by the
+//! various tools able to compute them.
+use crate::krate::Krate;
+use crate::prelude::*;
+use hax_frontend_exporter::Span;
+use std::fmt;
+
+pub use super::llvm_cov::LlvmCov;
+pub use super::tarpaulin::Tarpaulin;
+
+/// Represents the coverage status for a specific line in the source file.
+#[derive(Copy, Clone, Debug)]
+pub struct LineReport {
+    /// The line number within the source file.
+    pub line: usize,
+    /// Indicates whether the line is covered by a test.
+    pub covered: bool,
+}
+
+/// Distinguishes the decisions tracked by branch coverage.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum BranchKind {
+    /// A control-flow branch (e.g. an `if` or one operand of `&&`).
+    Branch,
+    /// A single condition within a boolean decision.
+    Condition,
+}
+
+/// Represents the coverage status of a branch or a condition.
+#[derive(Copy, Clone, Debug)]
+pub struct BranchReport {
+    /// The line number of the branch within the source file.
+    pub line: usize,
+    /// The column of the branch within its line, if known.
+    pub column: Option<usize>,
+    /// Whether this is a branch or a condition.
+    pub kind: BranchKind,
+    /// Indicates whether the branch was ever taken (or the condition ever true).
+    pub true_taken: bool,
+    /// Indicates whether the branch was ever skipped (or the condition ever false).
+    pub false_taken: bool,
+}
+
+impl BranchReport {
+    /// Whether both outcomes of the branch were observed.
+    pub fn covered(&self) -> bool {
+        self.true_taken && self.false_taken
+    }
+
+    /// Describes the outcomes that were never observed, if any.
+    pub fn missed(&self) -> Option<String> {
+        let what = match self.kind {
+            BranchKind::Branch => "branch",
+            BranchKind::Condition => "condition",
+        };
+        let message = match (self.true_taken, self.false_taken) {
+            (true, true) => return None,
+            (false, false) => format!("{what} never reached"),
+            (false, true) => format!("true {what} never taken"),
+            (true, false) => format!("false {what} never taken"),
+        };
+        Some(match self.column {
+            Some(column) => format!("column {column}: {message}"),
+            None => message,
+        })
+    }
+}
+
+/// Something in an item that tests can cover: an instrumented line,
+/// or one outcome of a branch or condition.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub enum CoveragePoint {
+    /// An instrumented line.
+    Line(usize),
+    /// An outcome of the `nth` branch (or condition) of the item.
+    Branch { nth: usize, outcome: bool },
+}
+
+/// Represents the coverage of a single source file.
+#[derive(Clone, Debug)]
+pub struct FileCoverage {
+    /// The absolute path of the file.
+    pub path: PathBuf,
+    /// The coverage status of the instrumented lines of the file.
+    pub lines: Vec<LineReport>,
+    /// The coverage status of the branches and conditions of the
+    /// file. This is empty if the backend doesn't track branches.
+    pub branches: Vec<BranchReport>,
+}
+
+/// Represents the coverage of a crate, as computed by a `CoverageBackend`.
+#[derive(Clone, Debug, Default)]
+pub struct CoverageData {
+    /// The coverage of every file instrumented by the backend.
+    pub files: Vec<FileCoverage>,
+}
+
+/// A tool able to compute the coverage of a crate when running some
+/// of its tests.
+pub trait CoverageBackend: std::fmt::Debug + Send + Sync {
+    /// The binary that needs to be in PATH for this backend to work.
+    fn required_binary(&self) -> &'static str;
+    /// Runs the tests of `krate` whose name contains `test` and
+    /// reports their coverage. The instrumented build of `krate` is
+    /// reused across calls, as long as its sources don't change.
+    fn coverage(&self, krate: &Krate, test: &str) -> CoverageData;
+}
+
+/// Represents the coverage report for a specific item (e.g.,
+/// function) within a crate. Note that reports are only about items
+/// that have bad coverage.
+#[derive(Clone, Debug)]
+pub struct BadCoverageReport {
+    /// The relative path of the source file containing the item.
+    pub relative_path: PathBuf,
+    /// The path of the item within the crate (e.g., function name).
+    pub item_path: String,
+    /// A list of lines in the item, including their coverage status.
+    pub lines: Vec<(usize, String, Option<bool>)>,
+    /// The branches and conditions of the item, including their coverage status.
+    pub branches: Vec<BranchReport>,
+    /// The minimum line coverage (in percent) required by the
+    /// contracts testing this item, if any.
+    pub min_coverage: Option<f64>,
+    /// The path of the source file containing the item, in the
+    /// original crate.
+    pub source_path: PathBuf,
+    /// The contents of the source file containing the item, as
+    /// covered: formatted, in the duplicated crate. Line numbers refer
+    /// to this source.
+    pub formatted_source: String,
+    /// The contracts that exercised the item.
+    pub contracts: Vec<ExercisingContract>,
+}
+
+/// A contract exercising an item, along with the assertions of its
+/// instances.
+#[derive(Clone, Debug)]
+pub struct ExercisingContract {
+    /// The description of the contract.
+    pub description: String,
+    /// The assertions run by the instances of the contract.
+    pub assertions: Vec<String>,
+}
+
+impl BadCoverageReport {
+    /// Returns the number of covered lines and the number of
+    /// instrumented lines of the item.
+    pub fn covered_lines(&self) -> (usize, usize) {
+        let instrumented = self.lines.iter().filter(|(_, _, c)| c.is_some());
+        let covered = instrumented.clone().filter(|(_, _, c)| *c == Some(true));
+        (covered.count(), instrumented.count())
+    }
+
+    /// The ratio of covered to instrumented lines, in percent.
+    pub fn coverage_percent(&self) -> f64 {
+        match self.covered_lines() {
+            (_, 0) => 100.0,
+            (covered, instrumented) => 100.0 * covered as f64 / instrumented as f64,
+        }
+    }
+
+    /// Returns the number of covered branches and the number of
+    /// branches of the item.
+    pub fn covered_branches(&self) -> (usize, usize) {
+        let covered = self.branches.iter().filter(|branch| branch.covered());
+        (covered.count(), self.branches.len())
+    }
+
+    /// Whether every instrumented line and every branch of the item is covered.
+    pub fn is_fully_covered(&self) -> bool {
+        let (covered, instrumented) = self.covered_lines();
+        let (covered_branches, branches) = self.covered_branches();
+        covered == instrumented && covered_branches == branches
+    }
+
+    /// Lists every coverage point of the item, along with whether it
+    /// was covered.
+    fn points_with_status(&self) -> impl Iterator<Item = (CoveragePoint, bool)> + '_ {
+        let lines = self
+            .lines
+            .iter()
+            .flat_map(|(n, _, c)| Some((CoveragePoint::Line(*n), (*c)?)));
+        let branches = self.branches.iter().enumerate().flat_map(|(nth, branch)| {
+            [
+                (
+                    CoveragePoint::Branch { nth, outcome: true },
+                    branch.true_taken,
+                ),
+                (
+                    CoveragePoint::Branch {
+                        nth,
+                        outcome: false,
+                    },
+                    branch.false_taken,
+                ),
+            ]
+        });
+        lines.chain(branches)
+    }
+
+    /// The coverage points of the item.
+    pub fn points(&self) -> HashSet<CoveragePoint> {
+        self.points_with_status().map(|(point, _)| point).collect()
+    }
+
+    /// The coverage points of the item that were covered.
+    pub fn covered_points(&self) -> HashSet<CoveragePoint> {
+        self.points_with_status()
+            .filter(|(_, covered)| *covered)
+            .map(|(point, _)| point)
+            .collect()
+    }
+}
+
+impl CoverageData {
+    /// Retrieves the coverage information for a specific file.
+    ///
+    /// This method returns a list of `LineReport` instances
+    /// representing the coverage status of each relevant line in the
+    /// specified file. Irrelevant lines are for instance blank lines
+    /// or commented lines.
+    pub fn lines_for_file(&self, file: &Path) -> Vec<LineReport> {
+        self.files
+            .iter()
+            .filter(|file_coverage| file_coverage.path == file)
+            .flat_map(|file_coverage| file_coverage.lines.iter().copied())
+            .collect()
+    }
+
+    /// Retrieves the branch and condition coverage information for a
+    /// specific file.
+    pub fn branches_for_file(&self, file: &Path) -> Vec<BranchReport> {
+        self.files
+            .iter()
+            .filter(|file_coverage| file_coverage.path == file)
+            .flat_map(|file_coverage| file_coverage.branches.iter().copied())
+            .collect()
+    }
+
+    /// Generates a coverage report for a specific span of code within
+    /// a file.
+    ///
+    /// This method returns a `Some` with a `BadCoverageReport` for
+    /// the item if any of the lines within the specified span are not
+    /// covered by tests.
+    ///
+    /// # Parameters
+    /// - `item_path`: The path to the item within the crate (e.g., `krate::path::to::function`).
+    /// - `file`: The path to the source file containing the item.
+    /// - `span`: The span of the item within the source file.
+    #[tracing::instrument(level = "trace")]
+    pub fn coverage_for_span(
+        &self,
+        item_path: String,
+        file: &Path,
+        span: Span,
+    ) -> Option<BadCoverageReport> {
+        let within_range = |line| line >= span.lo.line && line <= span.hi.line;
+        let line_reports = self.lines_for_file(&file);
+        let lines_status: HashMap<_, _> = line_reports
+            .iter()
+            .filter(|lr| within_range(lr.line))
+            .map(|lr| (lr.line, lr.covered))
+            .collect();
+        if lines_status.is_empty() {
+            return None;
+        }
+        let contents = std::fs::read_to_string(&file).unwrap();
+        let lines: Vec<_> = contents.lines().collect();
+        let lines = (span.lo.line..=span.hi.line).map(|line| {
+            (
+                line,
+                lines[line - 1].to_string(),
+                lines_status.get(&line).copied(),
+            )
+        });
+        let branches = self
+            .branches_for_file(&file)
+            .into_iter()
+            .filter(|branch| within_range(branch.line))
+            .collect();
+        Some(BadCoverageReport {
+            lines: lines.collect(),
+            branches,
+            item_path,
+            relative_path: span.filename.to_path().unwrap_or(file).to_path_buf(),
+            min_coverage: None,
+            source_path: file.to_path_buf(),
+            formatted_source: contents,
+            contracts: vec![],
+        })
+    }
+}
+
+impl fmt::Display for BadCoverageReport {
+    /// Formats the `BadCoverageReport` for display.
+    ///
+    /// This implementation provides a summary of the uncovered lines
+    /// in the item, including line numbers, coverage status, and
+    /// corresponding source code lines.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let branches = match self.covered_branches() {
+            (_, 0) => String::new(),
+            (covered, total) => format!(", {covered}/{total} branches"),
+        };
+        writeln!(
+            f,
+            "\n{}",
+            format!(
+                " ╭ ⚠ Item `{}` was not entirely covered ({:.1}% of lines{branches}).\n │ ↳ You need more contracts or more tests.",
+                format!("{}", self.item_path).reversed(),
+                self.coverage_percent()
+            )
+            .bold()
+        )?;
+        let width = self
+            .lines
+            .iter()
+            .map(|(n, _, _)| *n)
+            .max()
+            .unwrap_or(4)
+            .to_string()
+            .len();
+        let filler = std::iter::repeat_n(" ", width).collect::<String>();
+        let mut rows = vec![];
+        for (n, s, c) in self.lines.iter() {
+            let missed: Vec<_> = self
+                .branches
+                .iter()
+                .filter(|branch| branch.line == *n)
+                .flat_map(BranchReport::missed)
+                .collect();
+            let n = format!("{filler}{n}");
+            let n: String = format!("{filler}{n}")
+                .chars()
+                .skip(n.len() - width)
+                .collect();
+            let (c, s) = match c {
+                None => (" ".into(), s.dimmed()),
+                Some(true) if !missed.is_empty() => ("◐".yellow(), s.yellow()),
+                Some(true) => ("✓".green(), s.green()),
+                Some(false) => ("⨯".red(), s.red()),
+            };
+            rows.push(format!("{} {c} {s}", n.dimmed()));
+            for message in missed {
+                rows.push(format!("{filler}   {}", format!("↳ {message}").yellow()));
+            }
+        }
+        for (i, row) in rows.iter().enumerate() {
+            let box_char = if i + 1 == rows.len() { "╰" } else { "│" };
+            writeln!(f, " {box_char} {row}")?;
+        }
+        Ok(())
+    }
+}
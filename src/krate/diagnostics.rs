@@ -0,0 +1,381 @@
+//! This module traces the errors reported by the compiler on a
+//! generated crate back to the contracts they originate from. The
+//! fields of contracts are wrapped in labeled blocks (see `mark`),
+//! which are found again in the generated source, so that
+//! diagnostics can be rendered against the original text of the
+//! contracts.
+use crate::prelude::*;
+use cargo_metadata::diagnostic::{Diagnostic, DiagnosticSpan};
+use proc_macro2::LineColumn;
+use std::ops::Range;
+
+/// The prefix of the labels marking the fields of contracts.
+const LABEL_PREFIX: &str = "testify_contract_";
+
+/// The failure of `cargo` to build a generated crate.
+#[derive(Clone, Debug)]
+pub struct CompileError {
+    /// What `cargo` printed on stderr.
+    pub stderr: String,
+    /// The errors reported by the compiler on the crate itself.
+    pub diagnostics: Vec<Diagnostic>,
+    /// The source of the crate, i.e. its `main.rs`.
+    pub source: String,
+}
+
+/// The failure of a generated crate: it doesn't build, or its
+/// program doesn't run to completion.
+#[derive(Debug)]
+pub enum KrateError {
+    /// The crate doesn't compile.
+    Compile(CompileError),
+    /// The program of the crate crashed (e.g. it aborted, or
+    /// overflowed its stack), with the given message.
+    Crashed(String),
+    /// Hax failed on the crate, with the given stderr.
+    Hax(String),
+    /// A tool (e.g. `cargo`) is missing.
+    MissingTool(String),
+    /// The program of the crate (or `cargo`) could not be run.
+    Io(std::io::Error),
+}
+
+impl KrateError {
+    /// The failure to run `cargo`.
+    pub fn cargo(error: std::io::Error) -> Self {
+        match error.kind() {
+            std::io::ErrorKind::NotFound => Self::MissingTool("cargo".into()),
+            _ => Self::Io(error),
+        }
+    }
+
+    /// The error of the pipeline for this failure, which is about
+    /// `failed`, some of `contracts`.
+    pub fn into_error(
+        self,
+        failed: Vec<crate::error::ContractRef>,
+        contracts: &[&Contract],
+    ) -> crate::Error {
+        match self {
+            Self::Compile(error) => crate::Error::Compile {
+                contracts: failed,
+                stderr: error.render(contracts),
+            },
+            Self::Crashed(message) => crate::Error::Crashed {
+                contracts: failed,
+                message,
+            },
+            Self::Hax(stderr) => crate::Error::Hax {
+                contracts: failed,
+                stderr,
+            },
+            Self::MissingTool(tool) => crate::Error::MissingTool(tool),
+            Self::Io(error) => crate::Error::Io(error),
+        }
+    }
+}
+
+impl CompileError {
+    /// Renders the errors, against the text of `contracts` when they
+    /// come from a field marked with `mark`. Falls back to what
+    /// `cargo` printed when there is no diagnostic.
+    pub fn render(&self, contracts: &[&Contract]) -> String {
+        if self.diagnostics.is_empty() {
+            return self.stderr.clone();
+        }
+        let markers = markers(&self.source);
+        self.diagnostics
+            .iter()
+            .map(
+                |diagnostic| match locate(diagnostic, &self.source, &markers, contracts) {
+                    Some(origin) => origin.render(diagnostic, contracts),
+                    None => diagnostic
+                        .rendered
+                        .clone()
+                        .unwrap_or(diagnostic.message.clone()),
+                },
+            )
+            .join("\n")
+    }
+}
+
+/// Wraps `expr`, the field `field` of the `nth` contract, in a block
+/// labeled after the contract and the field.
+pub fn mark(nth: usize, field: &str, expr: impl ToTokens) -> proc_macro2::TokenStream {
+    let label = syn::Lifetime::new(
+        &format!("'{LABEL_PREFIX}{nth}_{field}"),
+        proc_macro2::Span::call_site(),
+    );
+    quote! {#label: {#expr}}
+}
+
+/// A field of a contract found in a generated source, along with the
+/// region it spans.
+struct Marker {
+    contract: usize,
+    field: String,
+    start: LineColumn,
+    end: LineColumn,
+}
+
+/// Finds the fields marked with `mark` in `source`.
+fn markers(source: &str) -> Vec<Marker> {
+    struct Collector(Vec<Marker>);
+    impl<'a> Visit<'a> for Collector {
+        fn visit_expr_block(&mut self, block: &'a syn::ExprBlock) {
+            use syn::spanned::Spanned;
+            let marker = block.label.as_ref().and_then(|label| {
+                let name = label.name.ident.to_string();
+                let (nth, field) = name.strip_prefix(LABEL_PREFIX)?.split_once('_')?;
+                Some(Marker {
+                    contract: nth.parse().ok()?,
+                    field: field.to_string(),
+                    start: block.span().start(),
+                    end: block.span().end(),
+                })
+            });
+            self.0.extend(marker);
+            syn::visit::visit_expr_block(self, block)
+        }
+    }
+    let Ok(file) = syn::parse_file(source) else {
+        return vec![];
+    };
+    let mut collector = Collector(vec![]);
+    collector.visit_file(&file);
+    collector.0
+}
+
+/// Where a diagnostic originates from in a contract.
+pub struct Origin {
+    /// The index of the contract.
+    pub contract: usize,
+    /// The field of the contract (e.g. `precondition`).
+    pub field: String,
+    /// The text of the generated source the diagnostic points at.
+    snippet: String,
+    /// The label of the primary span of the diagnostic.
+    label: Option<String>,
+}
+
+/// The primary span of a diagnostic.
+fn primary_span(diagnostic: &Diagnostic) -> Option<&DiagnosticSpan> {
+    diagnostic.spans.iter().find(|span| span.is_primary)
+}
+
+/// Finds the field of the contract a diagnostic on `source` points
+/// at, using the `markers` of `source`.
+fn locate(
+    diagnostic: &Diagnostic,
+    source: &str,
+    markers: &[Marker],
+    contracts: &[&Contract],
+) -> Option<Origin> {
+    let span = primary_span(diagnostic)?;
+    let position = LineColumn {
+        line: span.line_start,
+        column: span.column_start.saturating_sub(1),
+    };
+    let key = |lc: LineColumn| (lc.line, lc.column);
+    let marker = markers
+        .iter()
+        .filter(|marker| contracts.len() > marker.contract)
+        .filter(|marker| key(marker.start) <= key(position) && key(position) < key(marker.end))
+        // The innermost marker
+        .max_by_key(|marker| key(marker.start))?;
+    Some(Origin {
+        contract: marker.contract,
+        field: marker.field.clone(),
+        snippet: source
+            .get(span.byte_start as usize..span.byte_end as usize)
+            .unwrap_or_default()
+            .to_string(),
+        label: span.label.clone(),
+    })
+}
+
+/// Finds the diagnostics on `source` coming from the fields of
+/// `contracts` marked with `mark`.
+pub fn locate_all<'a>(
+    diagnostics: &'a [Diagnostic],
+    source: &str,
+    contracts: &[&Contract],
+) -> Vec<(&'a Diagnostic, Option<Origin>)> {
+    let markers = markers(source);
+    diagnostics
+        .iter()
+        .map(|diagnostic| (diagnostic, locate(diagnostic, source, &markers, contracts)))
+        .collect()
+}
+
+/// Finds `needle` in `haystack`, ignoring whitespaces.
+fn find_ignoring_whitespace(haystack: &str, needle: &str) -> Option<Range<usize>> {
+    let needle: Vec<char> = needle.chars().filter(|c| !c.is_whitespace()).collect();
+    if needle.is_empty() {
+        return None;
+    }
+    let chars: Vec<(usize, char)> = haystack
+        .char_indices()
+        .filter(|(_, c)| !c.is_whitespace())
+        .collect();
+    chars
+        .windows(needle.len())
+        .find(|window| window.iter().map(|(_, c)| *c).eq(needle.iter().copied()))
+        .map(|window| {
+            let (end, c) = window[window.len() - 1];
+            window[0].0..end + c.len_utf8()
+        })
+}
+
+/// The byte offset of `position` in `source`.
+fn offset_of(source: &str, position: LineColumn) -> Option<usize> {
+    let line_start = match position.line {
+        0 => return None,
+        1 => 0,
+        line => source.match_indices('\n').nth(line - 2)?.0 + 1,
+    };
+    let line = &source[line_start..];
+    Some(
+        line_start
+            + line
+                .char_indices()
+                .nth(position.column)
+                .map_or(line.len(), |(i, _)| i),
+    )
+}
+
+impl Origin {
+    /// Locates the diagnostic in the contracts file of `contract`:
+    /// returns the contents of the file and the byte range the
+    /// diagnostic points at.
+    fn in_file(&self, contract: &Contract) -> Option<(String, PathBuf, Range<usize>)> {
+        let span = contract.field_spans.get(&self.field)?;
+        let file = span.file.clone()?;
+        let source = std::fs::read_to_string(&file).ok()?;
+        let start = offset_of(&source, span.start)?;
+        let field = start..(start + span.bytes).min(source.len());
+        let range = find_ignoring_whitespace(&source[field.clone()], &self.snippet)
+            .map_or(field, |range| start + range.start..start + range.end);
+        Some((source, file, range))
+    }
+
+    /// The precise span the diagnostic points at in the contracts
+    /// file of `contract`, if known.
+    pub fn span(&self, contract: &Contract) -> Option<Span> {
+        let (source, file, range) = self.in_file(contract)?;
+        Some(Span::from_range(&source, Some(file), range))
+    }
+
+    /// Renders `diagnostic` against the text of the field of the
+    /// contract it originates from.
+    fn render(&self, diagnostic: &Diagnostic, contracts: &[&Contract]) -> String {
+        let contract = contracts[self.contract];
+        let location = format!(
+            "contract `{}`, field `{}`",
+            contract.description, self.field
+        );
+        // The text of the field, the byte range to underline, and a
+        // location header
+        let (text, range, header) = match self.in_file(contract) {
+            Some((source, file, range)) => {
+                let header = format!(
+                    "{}: {location}",
+                    Span::from_range(&source, Some(file), range.clone())
+                );
+                (source, range, header)
+            }
+            None => {
+                let text = match self.field.as_str() {
+                    "precondition" => contract.precondition.to_token_stream().to_string(),
+                    _ => contract.postcondition.to_token_stream().to_string(),
+                };
+                let range = find_ignoring_whitespace(&text, &self.snippet).unwrap_or(0..text.len());
+                (text, range, location)
+            }
+        };
+        let line_start = text[..range.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = text[range.start..]
+            .find('\n')
+            .map_or(text.len(), |i| range.start + i);
+        let line_number = text[..range.start].matches('\n').count() + 1;
+        let gutter = " ".repeat(line_number.to_string().len());
+        let underline = format!(
+            "{}{}",
+            " ".repeat(text[line_start..range.start].chars().count()),
+            "^".repeat(
+                text[range.start..range.end.min(line_end)]
+                    .chars()
+                    .count()
+                    .max(1)
+            ),
+        );
+
+        let mut rendered = match &diagnostic.code {
+            Some(code) => format!("error[{}]: {}\n", code.code, diagnostic.message),
+            None => format!("error: {}\n", diagnostic.message),
+        };
+        rendered += &format!("{gutter}--> {header}\n");
+        rendered += &format!("{gutter} |\n");
+        rendered += &format!("{line_number} | {}\n", &text[line_start..line_end]);
+        rendered += &format!(
+            "{gutter} | {underline} {}\n",
+            self.label.as_deref().unwrap_or_default()
+        );
+        for child in &diagnostic.children {
+            let level = format!("{:?}", child.level).to_lowercase();
+            rendered += &format!("{gutter} = {level}: {}\n", child.message);
+        }
+        rendered
+    }
+}
+
+#[test]
+fn render_against_contract() {
+    let contract: Contract = toml::from_str(
+        r#"
+        description = "d"
+        precondition = "true"
+        postcondition = "x + 1 == y"
+        function_tested = "krate::f"
+        inputs = []
+        "#,
+    )
+    .unwrap();
+    let postcondition = mark(0, "postcondition", &contract.postcondition);
+    let source = prettyplease::unparse(
+        &syn::parse2(quote! {
+            fn main() {
+                let _: bool = #postcondition;
+            }
+        })
+        .unwrap(),
+    );
+    let snippet = "x + 1";
+    let start = source.find(snippet).unwrap();
+    let line = source[..start].matches('\n').count() + 1;
+    let column = start - source[..start].rfind('\n').unwrap();
+    let diagnostic: Diagnostic = serde_json::from_value(serde_json::json!({
+        "message": "cannot add `{integer}` to `X`",
+        "code": {"code": "E0369", "explanation": null},
+        "level": "error",
+        "spans": [{
+            "file_name": "main.rs", "byte_start": start, "byte_end": start + snippet.len(),
+            "line_start": line, "line_end": line,
+            "column_start": column, "column_end": column + snippet.len(),
+            "is_primary": true, "text": [], "label": "X", "suggested_replacement": null,
+            "suggestion_applicability": null, "expansion": null
+        }],
+        "children": [],
+        "rendered": null
+    }))
+    .unwrap();
+    let error = CompileError {
+        stderr: String::new(),
+        diagnostics: vec![diagnostic],
+        source,
+    };
+    let rendered = error.render(&[&contract]);
+    assert!(rendered.contains("contract `d`, field `postcondition`"));
+    assert!(rendered.contains("| x + 1 == y\n"));
+    assert!(rendered.contains("| ^^^^^ X\n"));
+}
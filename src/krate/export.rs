@@ -0,0 +1,244 @@
+//! This module exports coverage reports to files that outlive a run:
+//! lcov `.info` tracefiles, and self-contained static HTML pages.
+//! Reports are scoped to the tested functions, and line numbers are
+//! the ones of the crate once formatted with `cargo fmt`: tracefiles
+//! point at copies of the formatted sources, one per report.
+use super::coverage::BadCoverageReport;
+use crate::prelude::*;
+use std::fmt::Write;
+
+/// The path of the copy of the formatted source of `report`, the
+/// `nth` report, within `sources`: the path of the original file,
+/// made relative, in a directory of its own.
+fn source_copy(sources: &Path, nth: usize, report: &BadCoverageReport) -> PathBuf {
+    let relative: PathBuf = report
+        .source_path
+        .components()
+        .filter(|component| matches!(component, std::path::Component::Normal(_)))
+        .collect();
+    sources.join(nth.to_string()).join(relative)
+}
+
+/// Renders coverage reports as an lcov tracefile. Line numbers refer
+/// to the formatted sources, so the source files of the reports are
+/// saved, formatted, in the directory `sources`, and the tracefile
+/// points at them. Each report gets its own copy, since the source
+/// of a report also holds the test module injected after its tested
+/// function, which shifts the lines of the functions below it.
+pub fn lcov(reports: &[BadCoverageReport], sources: &Path) -> std::io::Result<String> {
+    let mut out = String::new();
+    for (nth, report) in reports.iter().enumerate() {
+        let path = source_copy(sources, nth, report);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(&path, &report.formatted_source)?;
+        writeln!(out, "TN:").unwrap();
+        writeln!(out, "SF:{}", path.display()).unwrap();
+        let start = report.lines.first().map_or(0, |(n, _, _)| *n);
+        writeln!(out, "FN:{start},{}", report.item_path).unwrap();
+        let hit = report.covered_lines().0.min(1);
+        writeln!(out, "FNDA:{hit},{}", report.item_path).unwrap();
+        writeln!(out, "FNF:1").unwrap();
+        writeln!(out, "FNH:{hit}").unwrap();
+
+        let (mut found, mut hit) = (0, 0);
+        for (block, branch) in report.branches.iter().enumerate() {
+            for (nth, taken) in [branch.true_taken, branch.false_taken].iter().enumerate() {
+                let taken = match (branch.true_taken || branch.false_taken, taken) {
+                    (false, _) => "-",
+                    (true, true) => "1",
+                    (true, false) => "0",
+                };
+                writeln!(out, "BRDA:{},{block},{nth},{taken}", branch.line).unwrap();
+                found += 1;
+                hit += (taken == "1") as usize;
+            }
+        }
+        if found > 0 {
+            writeln!(out, "BRF:{found}").unwrap();
+            writeln!(out, "BRH:{hit}").unwrap();
+        }
+
+        let (mut found, mut hit) = (0, 0);
+        for (n, _, covered) in &report.lines {
+            let Some(covered) = covered else { continue };
+            writeln!(out, "DA:{n},{}", *covered as u8).unwrap();
+            found += 1;
+            hit += *covered as usize;
+        }
+        writeln!(out, "LF:{found}").unwrap();
+        writeln!(out, "LH:{hit}").unwrap();
+        writeln!(out, "end_of_record").unwrap();
+    }
+    Ok(out)
+}
+
+/// Escapes a string so that it can be embedded in HTML.
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; color: #222; }
+section { margin-bottom: 3em; }
+h2 code { background: #eee; padding: 0 .3em; }
+.summary { color: #555; }
+table.source { border-collapse: collapse; font-family: monospace; white-space: pre; }
+table.source td { padding: 0 .6em; }
+td.n { color: #999; text-align: right; user-select: none; }
+tr.covered { background: #e6ffed; }
+tr.uncovered { background: #ffeef0; }
+tr.partial { background: #fff8c5; }
+tr.neutral { color: #777; }
+.missed { color: #9a6700; font-style: italic; }
+details pre { background: #f6f8fa; padding: .5em; }
+"#;
+
+/// Renders coverage reports as a self-contained static HTML page.
+/// For each tested function, the page shows its source with covered
+/// and uncovered lines, and the contracts that exercised it.
+pub fn html(reports: &[BadCoverageReport]) -> String {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Testify coverage</title>\n<style>{STYLE}</style>\n</head>\n<body>\n<h1>Testify coverage</h1>"
+    )
+    .unwrap();
+    for report in reports {
+        let (covered, instrumented) = report.covered_lines();
+        let branches = match report.covered_branches() {
+            (_, 0) => String::new(),
+            (covered, total) => format!(", {covered}/{total} branches"),
+        };
+        writeln!(
+            out,
+            "<section>\n<h2><code>{}</code></h2>\n<p class=\"summary\">{} &mdash; {covered}/{instrumented} lines ({:.1}%){branches}</p>",
+            escape(&report.item_path),
+            escape(&report.source_path.display().to_string()),
+            report.coverage_percent(),
+        )
+        .unwrap();
+
+        writeln!(out, "<h3>Contracts</h3>\n<ul>").unwrap();
+        for contract in &report.contracts {
+            writeln!(
+                out,
+                "<li><details><summary>{} ({} tests)</summary><pre>{}</pre></details></li>",
+                escape(&contract.description),
+                contract.assertions.len(),
+                escape(&contract.assertions.join("\n")),
+            )
+            .unwrap();
+        }
+        writeln!(out, "</ul>\n<table class=\"source\">").unwrap();
+        for (n, line, covered) in &report.lines {
+            let missed: Vec<_> = report
+                .branches
+                .iter()
+                .filter(|branch| branch.line == *n)
+                .flat_map(|branch| branch.missed())
+                .collect();
+            let class = match covered {
+                None => "neutral",
+                Some(true) if !missed.is_empty() => "partial",
+                Some(true) => "covered",
+                Some(false) => "uncovered",
+            };
+            let missed = missed
+                .iter()
+                .map(|message| format!(" <span class=\"missed\">↳ {}</span>", escape(message)))
+                .join("");
+            writeln!(
+                out,
+                "<tr class=\"{class}\"><td class=\"n\">{n}</td><td>{}{missed}</td></tr>",
+                escape(line)
+            )
+            .unwrap();
+        }
+        writeln!(out, "</table>\n</section>").unwrap();
+    }
+    writeln!(out, "</body>\n</html>").unwrap();
+    out
+}
+
+#[test]
+fn lcov_tracefile() {
+    use super::coverage::{BranchKind, BranchReport};
+    let report = BadCoverageReport {
+        relative_path: "src/lib.rs".into(),
+        item_path: "krate::f".into(),
+        lines: vec![
+            (1, "fn f(x: bool) -> u8 {".into(), Some(true)),
+            (2, "    if x { 1 } else { 2 }".into(), Some(false)),
+            (3, "}".into(), None),
+        ],
+        branches: vec![BranchReport {
+            line: 2,
+            column: None,
+            kind: BranchKind::Branch,
+            true_taken: true,
+            false_taken: false,
+        }],
+        min_coverage: None,
+        source_path: "/krate/src/lib.rs".into(),
+        formatted_source: "fn f(x: bool) -> u8 {\n    if x { 1 } else { 2 }\n}\n".into(),
+        contracts: vec![],
+    };
+    let sources = tempfile::tempdir().unwrap();
+    let copy = sources.path().join("0/krate/src/lib.rs");
+    assert_eq!(
+        lcov(std::slice::from_ref(&report), sources.path()).unwrap(),
+        format!("TN:\nSF:{}\nFN:1,krate::f\nFNDA:1,krate::f\nFNF:1\nFNH:1\nBRDA:2,0,0,1\nBRDA:2,0,1,0\nBRF:2\nBRH:1\nDA:1,1\nDA:2,0\nLF:2\nLH:1\nend_of_record\n", copy.display())
+    );
+    assert_eq!(
+        std::fs::read_to_string(copy).unwrap(),
+        report.formatted_source
+    );
+}
+
+#[test]
+fn lcov_functions_of_the_same_file() {
+    // `g` is below `f`: in the crate where `f` is tested, the test
+    // module injected after `f` shifts the lines of `g`
+    let report = |item_path: &str, line: usize, formatted_source: &str| BadCoverageReport {
+        relative_path: "src/lib.rs".into(),
+        item_path: item_path.into(),
+        lines: vec![(line, "fn _() {}".into(), Some(true))],
+        branches: vec![],
+        min_coverage: None,
+        source_path: "/krate/src/lib.rs".into(),
+        formatted_source: formatted_source.into(),
+        contracts: vec![],
+    };
+    let reports = [
+        report(
+            "krate::f",
+            1,
+            "fn f() {}\nmod testify_tests {}\nfn g() {}\n",
+        ),
+        report(
+            "krate::g",
+            2,
+            "fn f() {}\nfn g() {}\nmod testify_tests {}\n",
+        ),
+    ];
+    let sources = tempfile::tempdir().unwrap();
+    let tracefile = lcov(&reports, sources.path()).unwrap();
+    for (nth, report) in reports.iter().enumerate() {
+        let copy = sources.path().join(format!("{nth}/krate/src/lib.rs"));
+        let (line, _, _) = &report.lines[0];
+        assert!(tracefile.contains(&format!(
+            "SF:{}\nFN:{line},{}\n",
+            copy.display(),
+            report.item_path
+        )));
+        let source = std::fs::read_to_string(copy).unwrap();
+        let name = report.item_path.strip_prefix("krate::").unwrap();
+        assert_eq!(
+            source.lines().nth(line - 1).unwrap(),
+            format!("fn {name}() {{}}")
+        );
+    }
+}
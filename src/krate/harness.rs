@@ -0,0 +1,406 @@
+//! This module provides `Harness`: a duplicate of the crate that
+//! defines a function tested by some contracts, in which tests can be
+//! injected right after the item defining that function.
+use super::coverage::{BadCoverageReport, CoverageBackend, ExercisingContract};
+use crate::krate::Krate;
+use crate::prelude::*;
+use crate::TestedFunction;
+use hax_frontend_exporter::Span;
+
+/// A duplicated crate, along with the location of the tested function.
+#[derive(Debug)]
+pub struct Harness {
+    /// The duplicated crate, which can be edited freely.
+    pub krate: Krate,
+    /// The name of the crate of the tested function.
+    pub krate_name: String,
+    /// The stringified path of the tested function.
+    pub fn_path: String,
+    /// The span of the tested function, as reported by hax.
+    pub span: Span,
+    /// The full path to the Rust file holding the tested function.
+    pub filepath: PathBuf,
+    /// The contents of `filepath`, before any test was injected.
+    original_source: String,
+    /// The path of the original crate, which was duplicated.
+    source_root: PathBuf,
+    /// The offset in `original_source` at which the test module is
+    /// inserted: the end of the item enclosing the tested function.
+    test_offset: usize,
+}
+
+impl Harness {
+    /// Duplicates the crate defining the function `tested`, and asks
+    /// hax about the span of that function. Fails if the crate cannot
+    /// be found among `dependencies`, or if the function cannot be
+    /// found in the crate.
+    pub fn new(
+        tested: &TestedFunction,
+        dependencies: &HashMap<String, DependencySpec>,
+    ) -> Result<Self, crate::Error> {
+        // The crate of the function we're testing
+        let krate_name = tested.krate().to_string();
+
+        // We duplicate the crate `krate_name` so that we can edit it freely
+        let (krate, source_root) = Krate::duplicate_dependency(&krate_name, dependencies)?;
+
+        // Stringify the path
+        let fn_path = tested.to_string();
+
+        // Ask hax about the span of the item `fn_path`
+        let span = {
+            let items = krate.hax().map_err(|error| error.into_error(vec![], &[]))?;
+            let item = tested
+                .resolve(&items, &krate.workspace_path())
+                .ok_or_else(|| crate::Error::MissingFunction {
+                    function: fn_path.clone(),
+                    krate: krate_name.clone(),
+                })?;
+            item.span
+        };
+
+        // Reconstruct the full path to the Rust file holding the item `fn_path`
+        let filepath = krate
+            .workspace_path()
+            .join(span.filename.to_path().unwrap());
+        let original_source = std::fs::read_to_string(&filepath)?;
+        let test_offset = enclosing_item_end(&original_source, span.lo.line)
+            .unwrap_or_else(|| original_source.as_str().offset_at_loc(&span.hi));
+
+        Ok(Self {
+            krate,
+            krate_name,
+            fn_path,
+            span,
+            filepath,
+            original_source,
+            source_root,
+            test_offset,
+        })
+    }
+
+    /// The source of the tested function.
+    pub fn function_source(&self) -> &str {
+        let source = self.original_source.as_str();
+        &source[source.offset_at_loc(&self.span.lo)..source.offset_at_loc(&self.span.hi)]
+    }
+
+    /// Inserts a test module running the assertions of `contracts`
+    /// right after the item enclosing the tested function (i.e. the
+    /// function itself, or its `impl` block). Any test previously
+    /// injected is replaced.
+    pub fn inject_test(&self, contracts: &[&Contract]) {
+        self.inject_test_with_function(contracts, self.function_source())
+    }
+
+    /// Replaces the source of the tested function with `function`,
+    /// and inserts a test module running the assertions of
+    /// `contracts` right after the item enclosing it. Any test
+    /// previously injected is replaced.
+    pub fn inject_test_with_function(&self, contracts: &[&Contract], function: &str) {
+        let test_module = test_module(&self.krate_name, contracts.iter().copied());
+        self.inject_module(&test_module, function)
+    }
+
+    /// Replaces the source of the tested function with `function`,
+    /// and inserts `test_module` right after the item enclosing it.
+    fn inject_module(&self, test_module: &str, function: &str) {
+        let source = self.original_source.as_str();
+        let lo = source.offset_at_loc(&self.span.lo);
+        let hi = source.offset_at_loc(&self.span.hi);
+        std::fs::write(
+            &self.filepath,
+            format!(
+                "{}{function}{}\n{test_module}{}",
+                &source[..lo],
+                &source[hi..self.test_offset],
+                &source[self.test_offset..]
+            ),
+        )
+        .unwrap();
+    }
+
+    /// Measures the coverage of the tested function when running the
+    /// assertions of `contracts`. Returns `None` when no line of the
+    /// function is instrumented.
+    pub fn coverage(
+        &self,
+        contracts: &[&Contract],
+        backend: &dyn CoverageBackend,
+    ) -> Option<BadCoverageReport> {
+        self.inject_test(contracts);
+
+        // Format the crate: we want the various control-flow branches
+        // to be on their own line, some backends give a per-line report
+        self.krate.fmt().unwrap();
+
+        self.report(backend.coverage(&self.krate, "testify_test"), contracts)
+    }
+
+    /// Measures the coverage of the tested function by the assertions
+    /// of each of `contracts`, on its own. Every contract gets its own
+    /// test, and the crate is built once. The backends only report the
+    /// coverage of a whole run, though: the backend is still run once
+    /// per contract, and each run has the fixed cost of starting the
+    /// backend and collecting its report.
+    pub fn coverage_per_contract(
+        &self,
+        contracts: &[&Contract],
+        backend: &dyn CoverageBackend,
+    ) -> Vec<Option<BadCoverageReport>> {
+        let test_module = test_cases_module(&self.krate_name, contracts);
+        self.inject_module(&test_module, self.function_source());
+        self.krate.fmt().unwrap();
+        contracts
+            .iter()
+            .enumerate()
+            .map(|(nth, contract)| {
+                let test = test_case_name(nth, contracts.len());
+                self.report(backend.coverage(&self.krate, &test), &[contract])
+            })
+            .collect()
+    }
+
+    /// Turns the coverage of the crate when running the assertions of
+    /// `contracts` into a report on the tested function. Returns
+    /// `None` when no line of the function is instrumented.
+    fn report(
+        &self,
+        coverage: super::coverage::CoverageData,
+        contracts: &[&Contract],
+    ) -> Option<BadCoverageReport> {
+        // Keep only the reports that are within the span of the
+        // tested function
+        let mut report =
+            coverage.coverage_for_span(self.fn_path.clone(), &self.filepath, self.span.clone())?;
+        // The strictest threshold among the contracts wins
+        report.min_coverage = contracts
+            .iter()
+            .flat_map(|contract| contract.min_coverage)
+            .reduce(f64::max);
+        report.source_path = match self.filepath.strip_prefix(self.krate.path()) {
+            Ok(relative) => self.source_root.join(relative),
+            Err(_) => self.filepath.clone(),
+        };
+        for contract in contracts {
+            let assertion = contract.as_assertion().to_string();
+            match report
+                .contracts
+                .iter_mut()
+                .find(|exercising| exercising.description == contract.description)
+            {
+                Some(exercising) => exercising.assertions.push(assertion),
+                None => report.contracts.push(ExercisingContract {
+                    description: contract.description.clone(),
+                    assertions: vec![assertion],
+                }),
+            }
+        }
+        trace!("report={:?}", report);
+        Some(report)
+    }
+}
+
+/// Builds a test module `testify_tests` with a unit test
+/// `testify_test` running the assertions of `contracts`. The module is
+/// meant to be inserted in the crate `krate_name`: paths to that crate
+/// are rewritten into `crate::` paths.
+pub fn test_module<'a>(
+    krate_name: &str,
+    contracts: impl IntoIterator<Item = &'a Contract>,
+) -> String {
+    let assertions: Vec<_> = contracts
+        .into_iter()
+        .map(|contract| assertion(krate_name, contract))
+        .collect();
+    let module = quote! {
+        #[cfg(test)]
+        mod testify_tests {
+            #[allow(unused_imports)]
+            use super::*;
+            #[test]
+            fn testify_test() {
+                #(#assertions)*
+            }
+        }
+    };
+    format!("{}", module.to_token_stream())
+}
+
+/// The name of the test of the `nth` of `count` contracts (see
+/// `test_cases_module`). No name is a substring of another, so that
+/// each test can be run on its own.
+fn test_case_name(nth: usize, count: usize) -> String {
+    format!("testify_case_{nth}_of_{count}")
+}
+
+/// Builds a test module `testify_tests` with one unit test per
+/// contract of `contracts`, named after `test_case_name` (see
+/// `test_module`).
+fn test_cases_module(krate_name: &str, contracts: &[&Contract]) -> String {
+    let tests = contracts.iter().enumerate().map(|(nth, contract)| {
+        let name = syn::Ident::new(
+            &test_case_name(nth, contracts.len()),
+            proc_macro2::Span::call_site(),
+        );
+        let assertion = assertion(krate_name, contract);
+        quote! {
+            #[test]
+            fn #name() {
+                #assertion
+            }
+        }
+    });
+    let module = quote! {
+        #[cfg(test)]
+        mod testify_tests {
+            #[allow(unused_imports)]
+            use super::*;
+            #(#tests)*
+        }
+    };
+    format!("{}", module.to_token_stream())
+}
+
+/// The assertion of `contract`, meant to be run within the crate
+/// `krate_name`.
+fn assertion(krate_name: &str, contract: &Contract) -> syn::Stmt {
+    let mut assertion: syn::Stmt = syn::parse2(contract.as_assertion()).unwrap();
+    CratePathRewriter { krate_name }.visit_stmt_mut(&mut assertion);
+    assertion
+}
+
+/// Rewrites the paths whose first segment is the crate `krate_name`
+/// into paths starting with `crate`, so that they can be used from
+/// within the crate itself.
+struct CratePathRewriter<'a> {
+    krate_name: &'a str,
+}
+
+impl CratePathRewriter<'_> {
+    /// Rewrites the paths of a token stream that cannot be parsed,
+    /// e.g. the arguments of a custom macro: an identifier
+    /// `krate_name` followed by `::` is a leading segment unless it is
+    /// preceded by `::` or `.`.
+    fn rewrite_tokens(&self, tokens: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        use proc_macro2::{Group, Ident, TokenTree};
+        let tokens: Vec<_> = tokens.into_iter().collect();
+        let is_punct = |tt: Option<&TokenTree>, ch| matches!(tt, Some(TokenTree::Punct(p)) if p.as_char() == ch);
+        let mut output: Vec<TokenTree> = vec![];
+        for (i, tt) in tokens.iter().enumerate() {
+            match tt {
+                TokenTree::Group(group) => {
+                    let mut new_group =
+                        Group::new(group.delimiter(), self.rewrite_tokens(group.stream()));
+                    new_group.set_span(group.span());
+                    output.push(TokenTree::Group(new_group));
+                }
+                TokenTree::Ident(ident)
+                    if ident == self.krate_name
+                        && is_punct(tokens.get(i + 1), ':')
+                        && is_punct(tokens.get(i + 2), ':')
+                        && !is_punct(output.last(), '.') =>
+                {
+                    let len = output.len();
+                    let after_colons = len >= 2
+                        && is_punct(output.get(len - 1), ':')
+                        && is_punct(output.get(len - 2), ':');
+                    if after_colons {
+                        // `a::krate_name::...` is not a leading segment,
+                        // but `::krate_name::...` is
+                        let before = len.checked_sub(3).and_then(|i| output.get(i));
+                        if matches!(before, Some(TokenTree::Ident(_))) || is_punct(before, '>') {
+                            output.push(tt.clone());
+                            continue;
+                        }
+                        output.truncate(len - 2);
+                    }
+                    output.push(TokenTree::Ident(Ident::new("crate", ident.span())));
+                }
+                _ => output.push(tt.clone()),
+            }
+        }
+        output.into_iter().collect()
+    }
+}
+
+impl VisitMut for CratePathRewriter<'_> {
+    fn visit_path_mut(&mut self, path: &mut syn::Path) {
+        if path.segments.len() > 1 && path.segments[0].ident == self.krate_name {
+            path.segments[0].ident = syn::Ident::new("crate", path.segments[0].ident.span());
+            path.leading_colon = None;
+        }
+        syn::visit_mut::visit_path_mut(self, path);
+    }
+
+    fn visit_macro_mut(&mut self, mac: &mut syn::Macro) {
+        // Most macros (e.g. `assert!`) take comma-separated
+        // expressions: their paths are rewritten as any other path
+        use syn::parse::Parser;
+        let parser = syn::punctuated::Punctuated::<syn::Expr, syn::Token![,]>::parse_terminated;
+        mac.tokens = match parser.parse2(mac.tokens.clone()) {
+            Ok(mut exprs) => {
+                exprs.iter_mut().for_each(|expr| self.visit_expr_mut(expr));
+                exprs.to_token_stream()
+            }
+            Err(_) => self.rewrite_tokens(mac.tokens.clone()),
+        };
+        syn::visit_mut::visit_macro_mut(self, mac);
+    }
+}
+
+/// Finds the end (as an offset in `source`) of the innermost item of
+/// `source` that spans over `line`.
+fn enclosing_item_end(source: &str, line: usize) -> Option<usize> {
+    use proc_macro2::LineColumn;
+    use syn::spanned::Spanned;
+    struct EnclosingItem {
+        line: usize,
+        end: Option<LineColumn>,
+    }
+    impl<'ast> Visit<'ast> for EnclosingItem {
+        fn visit_item(&mut self, item: &'ast syn::Item) {
+            let span = item.span();
+            if span.start().line <= self.line && self.line <= span.end().line {
+                self.end = Some(span.end());
+                syn::visit::visit_item(self, item);
+            }
+        }
+    }
+    let file = syn::parse_file(source).ok()?;
+    let mut visitor = EnclosingItem { line, end: None };
+    visitor.visit_file(&file);
+    let end = visitor.end?;
+    Some(source.offset_at_line_col(end.line, end.column))
+}
+
+#[test]
+fn crate_paths_rewriting() {
+    let contract: Contract = toml::from_str(
+        r#"
+        description = "krate"
+        postcondition = "krate::f(krate_x) == ::krate::m::g(x.krate) && panics!(krate::h(krate)) && m!(::krate::f => a::krate::f)"
+        function_tested = "krate::f"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        test_module("krate", [&contract]),
+        quote! {
+            #[cfg(test)]
+            mod testify_tests {
+                #[allow(unused_imports)]
+                use super::*;
+                #[test]
+                fn testify_test() {
+                    assert!(
+                        crate::f(krate_x) == crate::m::g(x.krate)
+                            && panics!(crate::h(krate))
+                            && m!(crate::f => a::krate::f)
+                    );
+                }
+            }
+        }
+        .to_string()
+    );
+}
@@ -1,3 +1,4 @@
+use super::diagnostics::KrateError;
 use super::*;
 
 #[derive(fmt_derive::Debug, Clone, Hash, Eq, PartialEq)]
@@ -66,12 +67,10 @@ impl quote::ToTokens for HaxQueryWithId {
 
 #[derive(Error, Debug)]
 pub enum HaxQueryError {
-    #[error(
-        "hax failed to run on queries `{queries:#?}`, it returned the following errors:\n\n```\n{stderr}\n```"
-    )]
+    #[error("hax failed to run on queries `{queries:#?}`")]
     HaxError {
         queries: Vec<HaxQuery>,
-        stderr: String,
+        error: KrateError,
     },
 }
 
@@ -97,10 +96,10 @@ pub fn execute_hax_queries(
             fn main(){}
         };
         krate.source(&format!("{source}"));
-        krate.hax()
+        krate.hax_cached()
     })
-    .map_err(|(queries, stderr)| HaxQueryError::HaxError {
-        stderr,
+    .map_err(|(queries, error)| HaxQueryError::HaxError {
+        error,
         queries: queries.iter().map(|x| x.0.clone()).collect(),
     })?;
     use hax_frontend_exporter::{DefPathItem, DisambiguatedDefPathItem};
@@ -0,0 +1,258 @@
+//! This module provides the `LlvmCov` coverage backend, which relies
+//! on rustc's source-based coverage (`-C instrument-coverage`) through
+//! `cargo-llvm-cov`. Unlike tarpaulin, source-based coverage is
+//! region-based: a line is considered covered only if every region
+//! starting on that line was executed.
+use super::coverage::{
+    BranchKind, BranchReport, CoverageBackend, CoverageData, FileCoverage, LineReport,
+};
+use crate::krate::Krate;
+use crate::prelude::*;
+
+/// This module wraps the JSON export format of `llvm-cov export`,
+/// parsing it in Rust datatypes.
+mod llvm_cov_export {
+    use super::*;
+
+    /// The root of a JSON export produced by `llvm-cov export`.
+    #[derive(Deserialize, Debug, Clone)]
+    pub struct Export {
+        /// One entry per binary that was instrumented.
+        pub data: Vec<ExportData>,
+    }
+
+    /// The coverage data of a binary.
+    #[derive(Deserialize, Debug, Clone)]
+    pub struct ExportData {
+        /// The coverage data of each source file.
+        pub files: Vec<ExportFile>,
+    }
+
+    /// The coverage data of a source file.
+    #[derive(Deserialize, Debug, Clone)]
+    pub struct ExportFile {
+        /// The absolute path of the file.
+        pub filename: PathBuf,
+        /// The coverage segments of the file, sorted by position.
+        pub segments: Vec<Segment>,
+        /// The branch regions of the file. Those are only present
+        /// when branch coverage is enabled.
+        #[serde(default)]
+        pub branches: Vec<Branch>,
+    }
+
+    /// A branch region, encoded as an array `[line_start, col_start,
+    /// line_end, col_end, true_count, false_count, file_id,
+    /// expanded_file_id, kind]`.
+    #[derive(Deserialize, Debug, Clone)]
+    #[serde(transparent)]
+    pub struct Branch(Vec<serde_json::Value>);
+
+    impl Branch {
+        fn field(&self, nth: usize) -> u64 {
+            self.0.get(nth).and_then(|v| v.as_u64()).unwrap_or(0)
+        }
+        pub fn line(&self) -> usize {
+            self.field(0) as usize
+        }
+        pub fn column(&self) -> usize {
+            self.field(1) as usize
+        }
+        pub fn true_count(&self) -> u64 {
+            self.field(4)
+        }
+        pub fn false_count(&self) -> u64 {
+            self.field(5)
+        }
+    }
+
+    /// A segment is the position at which a region starts or ends.
+    /// It is encoded as an array `[line, col, count, has_count,
+    /// is_region_entry, is_gap_region]`.
+    #[derive(Deserialize, Debug, Clone)]
+    #[serde(transparent)]
+    pub struct Segment(Vec<serde_json::Value>);
+
+    impl Segment {
+        fn field(&self, nth: usize) -> &serde_json::Value {
+            self.0.get(nth).unwrap_or(&serde_json::Value::Null)
+        }
+        pub fn line(&self) -> usize {
+            self.field(0).as_u64().unwrap_or(0) as usize
+        }
+        pub fn count(&self) -> u64 {
+            self.field(2).as_u64().unwrap_or(0)
+        }
+        pub fn has_count(&self) -> bool {
+            self.field(3).as_bool().unwrap_or(false)
+        }
+        pub fn is_region_entry(&self) -> bool {
+            self.field(4).as_bool().unwrap_or(false)
+        }
+        pub fn is_gap_region(&self) -> bool {
+            self.field(5).as_bool().unwrap_or(false)
+        }
+        /// Whether this segment starts a region that is mapped to some code.
+        pub fn is_start_of_region(&self) -> bool {
+            self.has_count() && self.is_region_entry() && !self.is_gap_region()
+        }
+    }
+
+    impl ExportFile {
+        /// Computes per-line coverage out of the segments of the
+        /// file, following the logic of `llvm-cov`: a line is
+        /// instrumented if a region starts on it or if it is
+        /// within a region. Since we want region granularity, a line
+        /// is covered only when every region starting on it was
+        /// executed.
+        pub fn lines(&self) -> Vec<LineReport> {
+            let Some(last_line) = self.segments.iter().map(Segment::line).max() else {
+                return vec![];
+            };
+            let mut reports = vec![];
+            let mut wrapped: Option<&Segment> = None;
+            let mut segments = self.segments.iter().peekable();
+            for line in 1..=last_line {
+                let mut starting = vec![];
+                while let Some(segment) = segments.next_if(|segment| segment.line() == line) {
+                    starting.push(segment);
+                }
+                let regions: Vec<_> = starting
+                    .iter()
+                    .filter(|segment| segment.is_start_of_region())
+                    .collect();
+                let wrapped_count = wrapped
+                    .filter(|segment| segment.has_count() && !segment.is_gap_region())
+                    .map(Segment::count);
+                if wrapped_count.is_some() || !regions.is_empty() {
+                    let count = regions
+                        .iter()
+                        .map(|segment| segment.count())
+                        .chain(wrapped_count)
+                        .max()
+                        .unwrap_or(0);
+                    reports.push(LineReport {
+                        line,
+                        covered: count > 0 && regions.iter().all(|segment| segment.count() > 0),
+                    });
+                }
+                if let Some(last) = starting.last() {
+                    wrapped = Some(last);
+                }
+            }
+            reports
+        }
+
+        /// Computes the branch coverage of the file.
+        pub fn branch_reports(&self) -> Vec<BranchReport> {
+            self.branches
+                .iter()
+                .map(|branch| BranchReport {
+                    line: branch.line(),
+                    column: Some(branch.column()),
+                    kind: BranchKind::Branch,
+                    true_taken: branch.true_count() > 0,
+                    false_taken: branch.false_count() > 0,
+                })
+                .collect()
+        }
+    }
+
+    impl Krate {
+        /// Runs `cargo llvm-cov` to generate a coverage report for this crate.
+        ///
+        /// This method invokes `cargo llvm-cov` on the tests whose
+        /// name contains `test`, and parses the output as an `Export`. On a
+        /// nightly toolchain, branch coverage is enabled as well.
+        ///
+        /// # Panics
+        /// This method will panic if it fails to run `cargo llvm-cov` or if the output cannot be parsed.
+        pub fn llvm_cov(&self, test: &str) -> Export {
+            let path = self.path().join("llvm-cov-report.json");
+            let mut llvm_cov = self.command("cargo");
+            llvm_cov.args(["llvm-cov", "--json", "--output-path"]);
+            llvm_cov.arg(&path);
+            if self.is_nightly() {
+                llvm_cov.arg("--branch");
+            }
+            llvm_cov.args(["--", test]);
+            let output = llvm_cov.output().unwrap();
+            use std::fs::File;
+            use std::io::BufReader;
+            let file = File::open(path).unwrap_or_else(|e| panic!("{e}: {output:#?}"));
+            let reader = BufReader::new(file);
+
+            let report = serde_json::from_reader(reader).unwrap();
+            trace!("llvm-cov report: {report:#?}");
+            report
+        }
+
+        /// Whether the toolchain used for this crate is a nightly one.
+        fn is_nightly(&self) -> bool {
+            let mut rustc = self.command("rustc");
+            rustc.arg("--version");
+            rustc
+                .output()
+                .map(|output| String::from_utf8_lossy(&output.stdout).contains("nightly"))
+                .unwrap_or(false)
+        }
+    }
+}
+
+pub use llvm_cov_export::*;
+
+/// The coverage backend that relies on rustc's source-based coverage,
+/// via `cargo-llvm-cov`. The coverage it provides is region-based.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct LlvmCov;
+
+impl From<Export> for CoverageData {
+    fn from(export: Export) -> Self {
+        Self {
+            files: export
+                .data
+                .iter()
+                .flat_map(|data| data.files.iter())
+                .map(|file| FileCoverage {
+                    path: file.filename.clone(),
+                    lines: file.lines(),
+                    branches: file.branch_reports(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl CoverageBackend for LlvmCov {
+    fn required_binary(&self) -> &'static str {
+        "cargo-llvm-cov"
+    }
+    fn coverage(&self, krate: &Krate, test: &str) -> CoverageData {
+        krate.llvm_cov(test).into()
+    }
+}
+
+#[test]
+fn lines_from_segments() {
+    // fn f(x: bool) -> u8 {     // line 1: region entry, executed
+    //     if x { 1 } else { 2 } // line 2: `{ 2 }` never executed
+    // }                         // line 3: within the function region
+    let file: ExportFile = serde_json::from_value(serde_json::json!({
+        "filename": "/src/lib.rs",
+        "segments": [
+            [1, 22, 1, true, true, false],
+            [2, 10, 1, true, true, false],
+            [2, 15, 1, true, false, false],
+            [2, 21, 0, true, true, false],
+            [2, 26, 1, true, false, false],
+            [3, 2, 0, false, false, false],
+        ],
+    }))
+    .unwrap();
+    let lines: Vec<_> = file
+        .lines()
+        .iter()
+        .map(|report| (report.line, report.covered))
+        .collect();
+    assert_eq!(lines, vec![(1, true), (2, false), (3, true)]);
+}
@@ -0,0 +1,216 @@
+//! This module turns the manifest of a crate into a standalone
+//! manifest, which can be used outside of the crate's directory and
+//! workspace: fields inherited from the workspace are resolved, and
+//! relative paths are made absolute.
+use crate::prelude::*;
+use toml::{Table, Value};
+
+/// The tables of a manifest that declare dependencies.
+const DEPENDENCY_TABLES: [&str; 3] = ["dependencies", "dev-dependencies", "build-dependencies"];
+
+/// The fields of `[package]` that hold paths.
+const PATH_FIELDS: [&str; 2] = ["readme", "license-file"];
+
+/// The workspace a crate is a member of.
+pub struct WorkspaceManifest<'a> {
+    /// The manifest of the workspace root.
+    pub manifest: &'a Table,
+    /// The directory of the workspace root.
+    pub root: &'a Path,
+}
+
+/// Makes a path relative to `base` absolute.
+fn absolute(path: &mut Value, base: &Path) {
+    if let Value::String(s) = path {
+        *s = base.join(&*s).to_string_lossy().into_owned();
+    }
+}
+
+/// Whether `value` is `{ workspace = true, ... }`.
+fn is_inherited(value: &Value) -> bool {
+    value.get("workspace").and_then(Value::as_bool) == Some(true)
+}
+
+/// Makes the path of a dependency (if any) absolute.
+fn absolute_dependency(mut spec: Value, base: &Path) -> Value {
+    if let Some(path) = spec.get_mut("path") {
+        absolute(path, base);
+    }
+    spec
+}
+
+/// Resolves a dependency declared as `{ workspace = true, ... }`: the
+/// declaration of the workspace is merged with the local keys
+/// (`features` are added, `optional` is kept).
+fn inherit_dependency(name: &str, local: &Value, workspace: &WorkspaceManifest) -> Value {
+    let inherited = workspace
+        .manifest
+        .get("workspace")
+        .and_then(|ws| ws.get("dependencies"))
+        .and_then(|deps| deps.get(name))
+        .unwrap_or_else(|| panic!("Dependency `{name}` is not declared by the workspace"));
+    let mut spec = match absolute_dependency(inherited.clone(), workspace.root) {
+        Value::String(version) => {
+            Table::from_iter([("version".to_string(), Value::String(version))])
+        }
+        Value::Table(table) => table,
+        value => panic!("Unexpected dependency specification {value:?}"),
+    };
+    for (key, value) in local.as_table().into_iter().flatten() {
+        match key.as_str() {
+            "workspace" => (),
+            "features" => {
+                let features = spec
+                    .entry("features")
+                    .or_insert_with(|| Value::Array(vec![]));
+                if let (Value::Array(features), Value::Array(extra)) = (features, value) {
+                    features.extend(extra.iter().cloned());
+                }
+            }
+            _ => {
+                spec.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    Value::Table(spec)
+}
+
+/// Resolves and makes absolute the dependencies of a dependency table.
+fn resolve_dependencies(
+    dependencies: &mut Value,
+    crate_dir: &Path,
+    workspace: Option<&WorkspaceManifest>,
+) {
+    let Value::Table(dependencies) = dependencies else {
+        return;
+    };
+    for (name, spec) in dependencies.iter_mut() {
+        *spec = match workspace {
+            Some(workspace) if is_inherited(spec) => inherit_dependency(name, spec, workspace),
+            _ => absolute_dependency(spec.clone(), crate_dir),
+        };
+    }
+}
+
+/// Turns `manifest`, the manifest of the crate in `crate_dir`, into
+/// a standalone manifest for a crate named `name`. When the crate is
+/// a member of `workspace`, inherited fields, dependencies and lints
+/// are resolved.
+pub fn standalone_manifest(
+    mut manifest: Table,
+    name: &str,
+    crate_dir: &Path,
+    workspace: Option<&WorkspaceManifest>,
+) -> Table {
+    let workspace_table = |key: &str| {
+        workspace
+            .and_then(|ws| ws.manifest.get("workspace"))
+            .and_then(|ws| ws.get(key))
+    };
+
+    if let Some(Value::Table(package)) = manifest.get_mut("package") {
+        for (key, value) in package.iter_mut() {
+            if is_inherited(value) {
+                *value = workspace_table("package")
+                    .and_then(|package| package.get(key))
+                    .cloned()
+                    .unwrap_or_else(|| {
+                        panic!("Field `package.{key}` is not declared by the workspace")
+                    });
+                if PATH_FIELDS.contains(&key.as_str()) {
+                    absolute(value, workspace.unwrap().root);
+                }
+            } else if PATH_FIELDS.contains(&key.as_str()) {
+                absolute(value, crate_dir);
+            }
+        }
+        package.insert("name".into(), Value::String(name.into()));
+        // The crate is now a member of the testify workspace
+        package.remove("workspace");
+    }
+
+    for table in DEPENDENCY_TABLES {
+        if let Some(dependencies) = manifest.get_mut(table) {
+            resolve_dependencies(dependencies, crate_dir, workspace);
+        }
+    }
+    if let Some(Value::Table(targets)) = manifest.get_mut("target") {
+        for (_, target) in targets.iter_mut() {
+            for table in DEPENDENCY_TABLES {
+                if let Some(dependencies) = target.get_mut(table) {
+                    resolve_dependencies(dependencies, crate_dir, workspace);
+                }
+            }
+        }
+    }
+
+    if manifest.get("lints").is_some_and(is_inherited) {
+        match workspace_table("lints") {
+            Some(lints) => manifest.insert("lints".into(), lints.clone()),
+            None => manifest.remove("lints"),
+        };
+    }
+
+    manifest.remove("workspace");
+    manifest
+}
+
+#[test]
+fn workspace_member_manifest() {
+    let workspace: Table = toml::from_str(
+        r#"
+        [workspace]
+        members = ["crates/*"]
+        [workspace.package]
+        version = "1.2.0"
+        edition = "2021"
+        [workspace.dependencies]
+        sibling = { path = "crates/sibling" }
+        serde = "1.0"
+        [workspace.lints.rust]
+        unsafe_code = "forbid"
+        "#,
+    )
+    .unwrap();
+    let member: Table = toml::from_str(
+        r#"
+        [package]
+        name = "member"
+        version.workspace = true
+        edition.workspace = true
+        [dependencies]
+        sibling.workspace = true
+        serde = { workspace = true, features = ["derive"] }
+        local = { path = "../local" }
+        [lints]
+        workspace = true
+        "#,
+    )
+    .unwrap();
+    let workspace = WorkspaceManifest {
+        manifest: &workspace,
+        root: Path::new("/ws"),
+    };
+    let manifest = standalone_manifest(
+        member,
+        "renamed",
+        Path::new("/ws/crates/member"),
+        Some(&workspace),
+    );
+    let expected: Table = toml::from_str(
+        r#"
+        [package]
+        name = "renamed"
+        version = "1.2.0"
+        edition = "2021"
+        [dependencies]
+        sibling = { path = "/ws/crates/sibling" }
+        serde = { version = "1.0", features = ["derive"] }
+        local = { path = "/ws/crates/member/../local" }
+        [lints.rust]
+        unsafe_code = "forbid"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(manifest, expected);
+}
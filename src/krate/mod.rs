@@ -2,34 +2,55 @@ use crate::prelude::*;
 use std::ffi::OsStr;
 use std::process::Command;
 
+pub mod coverage;
+pub mod diagnostics;
+pub mod export;
+pub mod harness;
 pub mod hax;
+pub mod llvm_cov;
+mod manifest;
+pub mod mutation;
 pub mod server;
 pub mod tarpaulin;
-mod workspace;
+pub mod workspace;
 
-use workspace::lock_workspace;
+use workspace::{lock_job_workspace, lock_workspace};
 
+/// A crate of the scratch workspace. The files of a crate are only
+/// written through its `Krate`, so they are written without locking
+/// the workspace: the workspace is locked only to add or remove
+/// crates.
 #[derive(Debug)]
 pub struct Krate {
     id: workspace::KrateId,
     dependencies: HashMap<String, DependencySpec>,
+    path: PathBuf,
 }
 
 impl Krate {
     pub fn new() -> Self {
-        let mut workspace = lock_workspace();
-        Self {
-            id: workspace.add_crate(),
+        let (id, path) = lock_workspace().reserve_crate();
+        let krate = Self {
+            id,
             dependencies: HashMap::new(),
-        }
+            path,
+        };
+        workspace::write_crate_manifest(&krate.path, &krate.name(), &krate.dependencies);
+        workspace::write_crate_main(&krate.path, "fn main() {}");
+        lock_job_workspace(krate.id.job()).register_crate(krate.id, None);
+        krate
     }
 
     pub fn workspace_path(&self) -> PathBuf {
-        let workspace = lock_workspace();
+        let workspace = lock_job_workspace(self.id.job());
         workspace.root.clone()
     }
 
-    /// Create a crate by using the source of an existing crate
+    /// Create a crate by using the source of an existing crate. When
+    /// the crate is a member of a workspace, the whole workspace is
+    /// copied, so that files shared with other members (e.g. used by
+    /// `build.rs` or `include_str!`) are available. The manifest of
+    /// the copy is made standalone (see [`manifest::standalone_manifest`]).
     pub fn duplicate_crate(
         path: &Path,
         extra_deps: &HashMap<String, DependencySpec>,
@@ -37,12 +58,18 @@ impl Krate {
         use std::path::Path;
         use std::{fs, io};
 
+        /// Directories holding build artifacts or VCS data.
+        const SKIPPED_DIRS: [&str; 5] = ["target", ".git", ".hg", ".svn", ".jj"];
+
         fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::Result<()> {
             fs::create_dir_all(&dst)?;
             for entry in fs::read_dir(src)? {
                 let entry = entry?;
                 let ty = entry.file_type()?;
                 if ty.is_dir() {
+                    if SKIPPED_DIRS.iter().any(|dir| entry.file_name() == *dir) {
+                        continue;
+                    }
                     copy_dir_all(entry.path(), dst.as_ref().join(entry.file_name()))?;
                 } else {
                     fs::copy(entry.path(), dst.as_ref().join(entry.file_name()))?;
@@ -51,20 +78,21 @@ impl Krate {
             Ok(())
         }
 
-        fn rename_crate(dir: &Path, name: &str) -> io::Result<()> {
-            let manifest_path = dir.join("Cargo.toml");
-            let manifest = fs::read_to_string(&manifest_path)?
-                .lines()
-                .map(|line| {
-                    if line.starts_with("name = ") {
-                        format!(r#"name = "{}""#, name)
-                    } else {
-                        line.to_string()
-                    }
-                })
-                .join("\n");
-            fs::write(&manifest_path, manifest)?;
-            Ok(())
+        fn read_manifest(path: &Path) -> io::Result<toml::Table> {
+            toml::from_str(&fs::read_to_string(path)?)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        }
+
+        /// Finds the root of the workspace `dir` is a member of, if
+        /// it is not `dir` itself.
+        fn workspace_root(dir: &Path) -> Option<PathBuf> {
+            let metadata = cargo_metadata::MetadataCommand::new()
+                .manifest_path(dir.join("Cargo.toml"))
+                .no_deps()
+                .exec()
+                .ok()?;
+            let root = metadata.workspace_root.into_std_path_buf();
+            (root != dir).then_some(root)
         }
 
         fn cargo_add(
@@ -72,28 +100,79 @@ impl Krate {
             extra_deps: &HashMap<String, DependencySpec>,
         ) -> io::Result<()> {
             let manifest_path = krate.path().join("Cargo.toml");
-            let mut manifest: toml::Value =
-                toml::from_str(&fs::read_to_string(&manifest_path)?).unwrap();
-            manifest["dependencies"] = manifest
-                .get("dependencies")
-                .cloned()
-                .unwrap_or(toml::value::Value::Table(toml::Table::default()));
-            let toml::Value::Table(dependencies) = &mut manifest["dependencies"] else {
-                panic!()
+            let mut manifest = read_manifest(&manifest_path)?;
+            let dependencies = manifest
+                .entry("dependencies")
+                .or_insert_with(|| toml::Value::Table(toml::Table::default()));
+            let toml::Value::Table(dependencies) = dependencies else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "`dependencies` is not a table in {}",
+                        manifest_path.display()
+                    ),
+                ));
             };
-            for (k, v) in extra_deps {
-                let v = toml::to_string(v).unwrap();
-                dependencies.insert(k.to_string(), toml::from_str(&v).unwrap());
+            for (k, DependencySpec(v)) in extra_deps {
+                dependencies.insert(k.to_string(), v.clone());
             }
             fs::write(&manifest_path, toml::to_string(&manifest).unwrap())?;
             Ok(())
         }
 
-        let krate = Self::new();
-        fs::remove_dir_all(krate.path())?;
-        copy_dir_all(&path, krate.path())?;
-        rename_crate(&krate.path(), &krate.name())?;
+        let path = fs::canonicalize(path)?;
+        let workspace_root = workspace_root(&path);
+        let subdir = workspace_root
+            .as_ref()
+            .map(|root| path.strip_prefix(root).unwrap().to_path_buf());
+        let (id, krate_dir) = lock_workspace().reserve_crate();
+        let krate = Self {
+            id,
+            dependencies: HashMap::new(),
+            path: subdir
+                .as_ref()
+                .map_or(krate_dir.clone(), |subdir| krate_dir.join(subdir)),
+        };
+        match &workspace_root {
+            Some(root) => {
+                copy_dir_all(root, &krate_dir)?;
+                // The copied workspace root must not claim the crate
+                // as one of its members
+                let root_manifest_path = krate_dir.join("Cargo.toml");
+                let mut root_manifest = read_manifest(&root_manifest_path)?;
+                if root_manifest.contains_key("package") {
+                    root_manifest.remove("workspace");
+                    fs::write(
+                        &root_manifest_path,
+                        toml::to_string(&root_manifest).unwrap(),
+                    )?;
+                } else {
+                    fs::remove_file(&root_manifest_path)?;
+                }
+            }
+            None => copy_dir_all(&path, &krate_dir)?,
+        }
+
+        let root_manifest = match &workspace_root {
+            Some(root) => Some(read_manifest(&root.join("Cargo.toml"))?),
+            None => None,
+        };
+        let workspace = workspace_root
+            .as_deref()
+            .zip(root_manifest.as_ref())
+            .map(|(root, manifest)| manifest::WorkspaceManifest { manifest, root });
+        let manifest = manifest::standalone_manifest(
+            read_manifest(&path.join("Cargo.toml"))?,
+            &krate.name(),
+            &path,
+            workspace.as_ref(),
+        );
+        fs::write(
+            krate.path().join("Cargo.toml"),
+            toml::to_string(&manifest).unwrap(),
+        )?;
         cargo_add(&krate, extra_deps)?;
+        lock_job_workspace(krate.id.job()).register_crate(krate.id, subdir);
         Ok(krate)
     }
 
@@ -105,8 +184,7 @@ impl Krate {
     }
 
     pub fn path(&self) -> PathBuf {
-        let workspace = lock_workspace();
-        workspace.crate_path(self.id)
+        self.path.clone()
     }
 
     pub fn name(&self) -> String {
@@ -141,21 +219,70 @@ impl Krate {
         Some(package.manifest_path.clone().into())
     }
 
+    /// Finds the source file of the library target of this crate
+    pub fn lib_path(&self) -> Option<PathBuf> {
+        let metadata = self.metadata().ok()?;
+        let manifest_path = self.path().join("Cargo.toml");
+        let package = metadata
+            .packages
+            .iter()
+            .find(|pkg| pkg.manifest_path == manifest_path)?;
+        let target = package
+            .targets
+            .iter()
+            .find(|target| target.is_kind(cargo_metadata::TargetKind::Lib))?;
+        Some(target.src_path.clone().into())
+    }
+
+    /// Finds the crate `name` in the dependency graph of
+    /// `dependencies`, and duplicates it so that it can be edited
+    /// freely. Returns the duplicate, along with the path of the
+    /// original crate, or an error if the crate cannot be found.
+    pub fn duplicate_dependency(
+        name: &str,
+        dependencies: &HashMap<String, DependencySpec>,
+    ) -> Result<(Self, PathBuf), crate::Error> {
+        // Find the full path to the source of the crate `name`.
+        let krate_path = {
+            // `krate` is a dummy crate whose dependencies are `dependencies`
+            let krate = {
+                let mut krate = Krate::new();
+                krate.add_dependencies(dependencies);
+                krate
+            };
+            // Runs `cargo metadata`, and finds the path to the
+            // `Cargo.toml` of the crate `name`.
+            let manifest_path =
+                krate
+                    .manifest_path_of_crate(name)
+                    .ok_or_else(|| crate::Error::MissingCrate {
+                        name: name.to_string(),
+                        available: dependencies.keys().cloned().sorted().collect(),
+                    })?;
+            // Returns the parent folder of the `Cargo.toml` manifest
+            manifest_path.parent().unwrap().to_path_buf()
+        };
+        Ok((
+            Self::duplicate_crate(&krate_path, dependencies)?,
+            krate_path,
+        ))
+    }
+
     /// Constructs a command whose current directory is correctly setup
     pub fn command<S: AsRef<OsStr>>(&self, program: S) -> Command {
         let mut command = Command::new(program);
         command.current_dir(self.path());
+        command.env("CARGO_TARGET_DIR", workspace::target_dir());
         command
     }
 
     pub fn run(&self) -> std::process::Child {
         use std::process::Stdio;
 
-        std::process::Command::new("cargo")
+        self.command("cargo")
             .arg("run")
             .arg("--quiet")
             .arg("--release")
-            .current_dir(self.path())
             .stdin(Stdio::piped())
             .stderr(Stdio::piped())
             // .stderr(Stdio::inherit())
@@ -164,35 +291,135 @@ impl Krate {
             .expect("Couldn't run `cargo build`")
     }
 
-    pub fn hax(
-        &self,
-    ) -> Result<Vec<hax_frontend_exporter::Item<hax_frontend_exporter::ThirBody>>, String> {
+    /// Builds the binary of the crate in release mode, returning its
+    /// path, or the errors of `cargo` if the build fails.
+    pub fn build_release(&self) -> Result<PathBuf, diagnostics::KrateError> {
+        let output = self
+            .command("cargo")
+            .args(["build", "--quiet", "--release", "--message-format=json"])
+            .output()
+            .map_err(diagnostics::KrateError::cargo)?;
+        if output.status.success() {
+            Ok(workspace::target_dir().join("release").join(self.name()))
+        } else {
+            Err(diagnostics::KrateError::Compile(
+                diagnostics::CompileError {
+                    stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+                    diagnostics: self.errors(&output.stdout),
+                    source: std::fs::read_to_string(self.path().join("main.rs"))
+                        .unwrap_or_default(),
+                },
+            ))
+        }
+    }
+
+    /// Runs `cargo check` on the crate, and returns the errors
+    /// reported by the compiler on the crate itself. Fails if `cargo`
+    /// cannot be run.
+    pub fn check(&self) -> std::io::Result<Vec<cargo_metadata::diagnostic::Diagnostic>> {
+        let output = self
+            .command("cargo")
+            .args(["check", "--quiet", "--message-format=json"])
+            .output()?;
+        Ok(self.errors(&output.stdout))
+    }
+
+    /// Parses the errors reported by the compiler on the crate itself
+    /// out of the JSON messages of `cargo`.
+    fn errors(&self, stdout: &[u8]) -> Vec<cargo_metadata::diagnostic::Diagnostic> {
+        use cargo_metadata::{diagnostic::DiagnosticLevel, Message};
+        let name = self.name();
+        Message::parse_stream(stdout)
+            .flatten()
+            .filter_map(|message| match message {
+                Message::CompilerMessage(message) if message.target.name == name => {
+                    Some(message.message)
+                }
+                _ => None,
+            })
+            .filter(|diagnostic| {
+                matches!(
+                    diagnostic.level,
+                    DiagnosticLevel::Error | DiagnosticLevel::Ice
+                )
+            })
+            .collect()
+    }
+
+    /// The cache key of a generated crate, computed out of its
+    /// `main.rs` and its dependencies.
+    pub fn cache_key(&self) -> crate::cache::Key {
+        let source = std::fs::read_to_string(self.path().join("main.rs")).unwrap_or_default();
+        crate::cache::Key::new(&source, &self.dependencies)
+    }
+
+    /// Runs hax on the crate, and returns its JSON output.
+    fn hax_json(&self) -> Result<String, diagnostics::KrateError> {
         use std::process::Stdio;
 
-        let output = std::process::Command::new("cargo")
+        let output = self
+            .command("cargo")
             .args(["hax", "json", "-o", "-"])
-            .current_dir(self.path())
             .stderr(Stdio::piped())
             .stdout(Stdio::piped())
             .env("RUST_LOG", "")
             .output()
-            .expect("Couldn't run `cargo run`");
-
-        let stdout = std::str::from_utf8(&output.stdout).unwrap();
-        let stderr = std::str::from_utf8(&output.stderr).unwrap();
+            .map_err(diagnostics::KrateError::cargo)?;
 
+        let stderr = String::from_utf8_lossy(&output.stderr);
         if output.status.success() {
-            let items: Vec<hax_frontend_exporter::Item<hax_frontend_exporter::ThirBody>> =
-                serde_json::from_str(&stdout).map_err(|e| {
-                    println!("{}", stdout);
-                    format!("Error parsing hax output: stdout:\n{stdout}\n\nstderr:{stderr}\n\nerror:{e:?}")
-                })?;
-            Ok(items)
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else if stderr.contains("no such command") {
+            Err(diagnostics::KrateError::MissingTool("cargo-hax".into()))
         } else {
-            Err(stderr.to_string())
+            Err(diagnostics::KrateError::Hax(stderr.into_owned()))
         }
     }
 
+    fn parse_hax_json(
+        stdout: &str,
+    ) -> Result<
+        Vec<hax_frontend_exporter::Item<hax_frontend_exporter::ThirBody>>,
+        diagnostics::KrateError,
+    > {
+        serde_json::from_str(stdout).map_err(|e| {
+            diagnostics::KrateError::Hax(format!(
+                "Error parsing hax output: stdout:\n{stdout}\n\nerror:{e:?}"
+            ))
+        })
+    }
+
+    /// Runs hax on the crate. Fails if hax (or `cargo`) cannot be
+    /// run, or if hax fails on the crate.
+    pub fn hax(
+        &self,
+    ) -> Result<
+        Vec<hax_frontend_exporter::Item<hax_frontend_exporter::ThirBody>>,
+        diagnostics::KrateError,
+    > {
+        Self::parse_hax_json(&self.hax_json()?)
+    }
+
+    /// Same as `hax`, for a generated crate: the output of hax is
+    /// looked up in the cache first (see [`Krate::cache_key`]).
+    pub fn hax_cached(
+        &self,
+    ) -> Result<
+        Vec<hax_frontend_exporter::Item<hax_frontend_exporter::ThirBody>>,
+        diagnostics::KrateError,
+    > {
+        let key = self.cache_key();
+        let stdout = match crate::cache::get::<String>("hax", key) {
+            Some(stdout) => stdout,
+            None => {
+                let stdout = self.hax_json()?;
+                crate::cache::put("hax", key, &stdout);
+                stdout
+            }
+        };
+        Self::parse_hax_json(&stdout)
+    }
+
     pub fn use_serde(&mut self) {
         self.add_dependencies(
             &toml::from_str(
@@ -210,20 +437,18 @@ serde = { version = "1.0", features = ["derive"] }
         }
     }
     pub fn add_dependency(&mut self, dep: &str, spec: &DependencySpec) {
-        let workspace = lock_workspace();
         self.dependencies.insert(dep.to_string(), spec.clone());
-        workspace.write_crate_manifest(self.id, &self.dependencies)
+        workspace::write_crate_manifest(&self.path, &self.name(), &self.dependencies)
     }
     pub fn source(&self, source: &str) {
         let source = prettyplease::unparse(&syn::parse_str(source).expect(source));
-        let workspace = lock_workspace();
-        workspace.write_crate_main(self.id, &source)
+        workspace::write_crate_main(&self.path, &source)
     }
 }
 
 impl Drop for Krate {
     fn drop(&mut self) {
-        let mut workspace = lock_workspace();
+        let mut workspace = lock_job_workspace(self.id.job());
         workspace.remove_crate(self.id);
     }
 }
@@ -233,7 +458,7 @@ impl Drop for Krate {
 /// which item among all items `items`. `run_or_catch_error(items, f)`
 /// will call `f` on `items`: if this fails, then it will repeatedly
 /// call `f` to spot the first item which is yielding an error.
-pub fn run_or_locate_error<'a, Item: std::fmt::Debug, Output, Error: Clone>(
+pub fn run_or_locate_error<'a, Item: std::fmt::Debug, Output, Error>(
     items: &'a [Item],
     mut f: impl FnMut(&'a [Item]) -> Result<Output, Error>,
 ) -> Result<Output, (Vec<&'a Item>, Error)> {
@@ -249,11 +474,11 @@ pub fn run_or_locate_error<'a, Item: std::fmt::Debug, Output, Error: Clone>(
                 }
             }
             Err(err) => {
-                let err = (items.iter().collect(), err.clone());
+                let err = (items.iter().collect(), err);
                 match items {
                     [] => unreachable!(),
                     [_item] => {
-                        return Err(err.clone());
+                        return Err(err);
                     }
                     _ => {
                         let (left, right) = items.split_at(items.len() / 2);
@@ -0,0 +1,354 @@
+//! This module provides mutation testing: small syntactic changes
+//! (mutants) are applied to a tested function, and the tests
+//! generated from the contracts are run against each mutant. A mutant
+//! that survives (i.e. that the tests don't detect) is evidence that
+//! the contracts are too weak.
+use super::harness::Harness;
+use crate::prelude::*;
+use proc_macro2::LineColumn;
+use std::fmt;
+use std::time::{Duration, Instant};
+use syn::spanned::Spanned;
+
+/// The kinds of mutations applied to a function.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MutationKind {
+    /// An arithmetic, bitwise or logical operator is swapped (e.g. `+` for `-`).
+    Operator,
+    /// A literal is replaced (e.g. `1` by `0`, `true` by `false`).
+    Constant,
+    /// A comparison is shifted by one (e.g. `<` for `<=`).
+    Boundary,
+}
+
+/// A syntactic change to the source of a function.
+#[derive(Clone, Debug)]
+pub struct Mutation {
+    /// The kind of the mutation.
+    pub kind: MutationKind,
+    /// The line of the mutation in the source file.
+    pub line: usize,
+    /// The original code.
+    pub original: String,
+    /// The code replacing the original one.
+    pub replacement: String,
+    /// The start of the mutated code, relative to the function.
+    start: LineColumn,
+    /// The end of the mutated code, relative to the function.
+    end: LineColumn,
+}
+
+impl Mutation {
+    /// Applies the mutation to `function`, the source of a function.
+    fn apply(&self, function: &str) -> String {
+        let start = function.offset_at_line_col(self.start.line, self.start.column);
+        let end = function.offset_at_line_col(self.end.line, self.end.column);
+        format!(
+            "{}{}{}",
+            &function[..start],
+            self.replacement,
+            &function[end..]
+        )
+    }
+}
+
+/// Collects the mutations of a function body.
+struct MutationCollector {
+    /// The line of the function in its source file.
+    first_line: usize,
+    mutations: Vec<Mutation>,
+}
+
+impl MutationCollector {
+    fn push(
+        &mut self,
+        kind: MutationKind,
+        span: proc_macro2::Span,
+        original: String,
+        replacement: &str,
+    ) {
+        self.mutations.push(Mutation {
+            kind,
+            line: self.first_line + span.start().line - 1,
+            original,
+            replacement: replacement.to_string(),
+            start: span.start(),
+            end: span.end(),
+        })
+    }
+}
+
+impl<'ast> Visit<'ast> for MutationCollector {
+    fn visit_bin_op(&mut self, op: &'ast syn::BinOp) {
+        use syn::BinOp::*;
+        use MutationKind::*;
+        let replacements: &[(MutationKind, &str)] = match op {
+            Add(_) => &[(Operator, "-"), (Operator, "*")],
+            Sub(_) => &[(Operator, "+"), (Operator, "/")],
+            Mul(_) => &[(Operator, "/"), (Operator, "+")],
+            Div(_) => &[(Operator, "*"), (Operator, "%")],
+            Rem(_) => &[(Operator, "/")],
+            And(_) => &[(Operator, "||")],
+            Or(_) => &[(Operator, "&&")],
+            BitXor(_) => &[(Operator, "|")],
+            BitAnd(_) => &[(Operator, "|")],
+            BitOr(_) => &[(Operator, "&")],
+            Shl(_) => &[(Operator, ">>")],
+            Shr(_) => &[(Operator, "<<")],
+            Eq(_) => &[(Operator, "!=")],
+            Ne(_) => &[(Operator, "==")],
+            Lt(_) => &[(Boundary, "<="), (Operator, ">")],
+            Le(_) => &[(Boundary, "<"), (Operator, ">")],
+            Gt(_) => &[(Boundary, ">="), (Operator, "<")],
+            Ge(_) => &[(Boundary, ">"), (Operator, "<")],
+            _ => &[],
+        };
+        let original = op.to_token_stream().to_string();
+        for (kind, replacement) in replacements {
+            self.push(*kind, op.span(), original.clone(), replacement);
+        }
+        syn::visit::visit_bin_op(self, op);
+    }
+
+    fn visit_lit(&mut self, lit: &'ast syn::Lit) {
+        let original = lit.to_token_stream().to_string();
+        match lit {
+            syn::Lit::Int(int) => {
+                let suffix = int.suffix();
+                let replacements = match int.base10_parse::<u128>() {
+                    Ok(0) => vec!["1"],
+                    Ok(1) => vec!["0"],
+                    Ok(_) => vec!["0", "1"],
+                    Err(_) => vec![],
+                };
+                for replacement in replacements {
+                    let replacement = format!("{replacement}{suffix}");
+                    self.push(
+                        MutationKind::Constant,
+                        lit.span(),
+                        original.clone(),
+                        &replacement,
+                    );
+                }
+            }
+            syn::Lit::Bool(b) => {
+                let replacement = if b.value { "false" } else { "true" };
+                self.push(MutationKind::Constant, lit.span(), original, replacement);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// The outcome of running the tests against a mutant.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MutantOutcome {
+    /// The tests failed: the mutant was detected.
+    Killed,
+    /// The tests passed: the mutant went unnoticed.
+    Survived,
+    /// The tests did not finish in time, which counts as a detection.
+    Timeout,
+    /// The mutant does not compile.
+    Unviable,
+}
+
+/// The mutation testing report for a tested function.
+#[derive(Clone, Debug)]
+pub struct MutationReport {
+    /// The relative path of the source file containing the function.
+    pub relative_path: PathBuf,
+    /// The path of the function.
+    pub item_path: String,
+    /// The mutants that were tried, with their outcome.
+    pub mutants: Vec<(Mutation, MutantOutcome)>,
+}
+
+impl MutationReport {
+    /// The mutants that went unnoticed by the tests.
+    pub fn survivors(&self) -> impl Iterator<Item = &Mutation> {
+        self.mutants
+            .iter()
+            .filter(|(_, outcome)| *outcome == MutantOutcome::Survived)
+            .map(|(mutation, _)| mutation)
+    }
+
+    /// The number of viable mutants.
+    pub fn viable(&self) -> usize {
+        self.mutants
+            .iter()
+            .filter(|(_, outcome)| *outcome != MutantOutcome::Unviable)
+            .count()
+    }
+}
+
+/// Runs `testify_test` with the test binaries `binaries` of the crate
+/// of `harness`, and kills them after `timeout`. The binaries are run
+/// directly rather than through `cargo test`, so that killing them
+/// doesn't leave a test process behind.
+fn run_test(harness: &Harness, binaries: &[PathBuf], timeout: Duration) -> MutantOutcome {
+    use std::process::Stdio;
+    let deadline = Instant::now() + timeout;
+    for binary in binaries {
+        let mut test = harness.krate.command(binary);
+        test.arg("testify_test")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        let Ok(mut child) = test.spawn() else {
+            return MutantOutcome::Killed;
+        };
+        let status = loop {
+            if let Some(status) = child.try_wait().unwrap() {
+                break status;
+            }
+            if Instant::now() > deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                return MutantOutcome::Timeout;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        };
+        if !status.success() {
+            return MutantOutcome::Killed;
+        }
+    }
+    MutantOutcome::Survived
+}
+
+/// Builds the tests of the crate of `harness`, returning the paths of
+/// the test binaries, or `None` when the build fails.
+fn build_tests(harness: &Harness) -> Option<Vec<PathBuf>> {
+    let mut build = harness.krate.command("cargo");
+    build.args(["test", "--no-run", "--message-format=json"]);
+    let output = build.output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let binaries = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter(|message| {
+            message["reason"] == "compiler-artifact" && message["profile"]["test"] == true
+        })
+        .filter_map(|message| Some(PathBuf::from(message["executable"].as_str()?)))
+        .collect();
+    Some(binaries)
+}
+
+impl Harness {
+    /// Lists the mutations of the body of the tested function.
+    pub fn mutations(&self) -> Vec<Mutation> {
+        let Ok(function) = syn::parse_str::<syn::ImplItemFn>(self.function_source()) else {
+            return vec![];
+        };
+        let mut collector = MutationCollector {
+            first_line: self.span.lo.line,
+            mutations: vec![],
+        };
+        collector.visit_block(&function.block);
+        collector.mutations
+    }
+
+    /// Runs the assertions of `contracts` against every mutant of the
+    /// tested function. Returns `None` when the assertions don't pass
+    /// on the original function.
+    pub fn mutation_testing(
+        &self,
+        contracts: &[&Contract],
+        timeout: Duration,
+    ) -> Option<MutationReport> {
+        self.inject_test(contracts);
+        let binaries = build_tests(self)?;
+        if run_test(self, &binaries, timeout) != MutantOutcome::Survived {
+            return None;
+        }
+        let function = self.function_source();
+        let mutants = self
+            .mutations()
+            .into_iter()
+            .map(|mutation| {
+                trace!("mutation={:?}", mutation);
+                self.inject_test_with_function(contracts, &mutation.apply(function));
+                let outcome = match build_tests(self) {
+                    Some(binaries) => run_test(self, &binaries, timeout),
+                    None => MutantOutcome::Unviable,
+                };
+                (mutation, outcome)
+            })
+            .collect();
+        // Leave the crate as it was
+        self.inject_test(contracts);
+        Some(MutationReport {
+            relative_path: self.span.filename.to_path()?.to_path_buf(),
+            item_path: self.fn_path.clone(),
+            mutants,
+        })
+    }
+}
+
+impl fmt::Display for MutationReport {
+    /// Formats the surviving mutants of the report.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let survivors: Vec<_> = self.survivors().collect();
+        let viable = self.viable();
+        let header = match survivors.len() {
+            0 => format!(
+                " ╭ ✓ Item `{}`: all {viable} mutants were killed.",
+                self.item_path
+            )
+            .green(),
+            n => format!(
+                " ╭ ⚠ Item `{}`: {n} out of {viable} mutants survived.\n │ ↳ Your contracts may be too weak.",
+                self.item_path
+            )
+            .bold(),
+        };
+        writeln!(f, "\n{header}")?;
+        for (i, mutation) in survivors.iter().enumerate() {
+            let box_char = if i + 1 == survivors.len() {
+                "╰"
+            } else {
+                "│"
+            };
+            let kind = match mutation.kind {
+                MutationKind::Operator => "operator",
+                MutationKind::Constant => "constant",
+                MutationKind::Boundary => "boundary",
+            };
+            writeln!(
+                f,
+                " {box_char} {}:{} {} `{}` → `{}`",
+                self.relative_path.display(),
+                mutation.line,
+                format!("({kind})").dimmed(),
+                mutation.original.red(),
+                mutation.replacement.yellow(),
+            )?;
+        }
+        if survivors.is_empty() {
+            writeln!(f, " ╰")?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn mutations_of_a_function() {
+    let function = "fn f(x: u8) -> bool {\n    x + 1 < 10\n}";
+    let block: syn::ImplItemFn = syn::parse_str(function).unwrap();
+    let mut collector = MutationCollector {
+        first_line: 3,
+        mutations: vec![],
+    };
+    collector.visit_block(&block.block);
+    let mutants: Vec<_> = collector
+        .mutations
+        .iter()
+        .map(|mutation| (mutation.line, mutation.apply(function)))
+        .collect();
+    assert_eq!(mutants.len(), 7);
+    assert!(mutants.iter().all(|(line, _)| *line == 4));
+    assert!(mutants.contains(&(4, "fn f(x: u8) -> bool {\n    x + 1 <= 10\n}".to_string())));
+    assert!(mutants.contains(&(4, "fn f(x: u8) -> bool {\n    x - 1 < 10\n}".to_string())));
+    assert!(mutants.contains(&(4, "fn f(x: u8) -> bool {\n    x + 0 < 10\n}".to_string())));
+}
@@ -1,11 +1,14 @@
 //! This module provides `Server`, a helper struct that implements a
 //! syncronous client/server logic.
 
-use crate::krate::Krate;
+use crate::krate::{diagnostics::KrateError, Krate};
 use crate::prelude::*;
 use quote::quote;
 use std::io::{BufRead, BufReader, BufWriter};
-use std::process::{ChildStderr, ChildStdin, ChildStdout};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 /// `declare!(Name, <tokens>)` defines a struct `Name` that implement
 /// `quote::ToTokens`: when converted into a token stream, `Name`
@@ -25,10 +28,50 @@ macro_rules! declare {
 }
 pub(crate) use declare;
 
+/// The time a server is given to answer a request, by default.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The ways a request to a server can fail. In each case, the
+/// server is restarted, so that further requests can be made.
+#[derive(Error, Debug, Clone)]
+pub enum ServerError {
+    #[error("the server did not answer within {0:?}")]
+    Timeout(Duration),
+    #[error("the server crashed, its stderr was:\n```\n{stderr}```")]
+    Crashed { stderr: String },
+    #[error(
+        "the server sent a response that could not be parsed ({error}):\n```\n{response}\n```\nits stderr was:\n```\n{stderr}```"
+    )]
+    InvalidResponse {
+        response: String,
+        stderr: String,
+        error: String,
+    },
+    #[error("the server could not be restarted: {0}")]
+    Restart(String),
+}
+
+/// A running server, along with its stdin, the lines it prints on
+/// stdout and what it printed on stderr so far.
+type Process = (
+    Child,
+    BufWriter<ChildStdin>,
+    Receiver<String>,
+    Arc<Mutex<String>>,
+);
+
 pub struct Server {
-    stdout: BufReader<ChildStdout>,
-    stderr: BufReader<ChildStderr>,
+    process: Child,
+    /// The lines printed by the server on stdout, read by a thread.
+    stdout: Receiver<String>,
+    /// What the server printed on stderr so far, read by a thread.
+    stderr: Arc<Mutex<String>>,
     stdin: BufWriter<ChildStdin>,
+    /// The binary of the server, used to restart it without building
+    /// it again.
+    binary: PathBuf,
+    /// The time the server is given to answer a request.
+    timeout: Duration,
     /// `krate` is not used explicitly, but it is important to keep it around: the crate will be cleaned up on drop
     _krate: Krate,
 }
@@ -38,11 +81,12 @@ impl Server {
     /// consume a `request` free variable. `body` should be of a type
     /// serializable by `serde`. The type of `request` needs to be
     /// constrainted in `body`: it can be anything that is
-    /// deserializable by `serde`.
+    /// deserializable by `serde`. Returns the errors of `cargo` if
+    /// the server fails to build.
     pub fn from_json_fn(
         body: impl quote::ToTokens,
         deps: &HashMap<String, DependencySpec>,
-    ) -> Self {
+    ) -> Result<Self, KrateError> {
         Self::from_string_fn(
             quote! {
                 let request = serde_json::from_str(&request).unwrap();
@@ -54,23 +98,26 @@ impl Server {
     }
     /// Creates a `Server` out of a block of code `body` that can
     /// consume a `request` free variable of type `String`. The type
-    /// of `body` should be `String`.
+    /// of `body` should be `String`. Returns the errors of `cargo` if
+    /// the server fails to build.
     pub fn from_string_fn(
         body: impl quote::ToTokens,
         deps: &HashMap<String, DependencySpec>,
-    ) -> Self {
+    ) -> Result<Self, KrateError> {
         let program = quote! {
             fn main() {
                 use std::io::*;
                 let mut reader = BufReader::new(stdin());
                 loop {
                     let mut s = String::new();
-                    if reader.read_line(&mut s).is_ok() {
-                        let request = s.trim();
-                        let anwser = { #body };
-                        println!("{}", anwser);
-                    } else {
-                        eprintln!("Server: could not read line");
+                    match reader.read_line(&mut s) {
+                        Ok(0) => break,
+                        Ok(_) => {
+                            let request = s.trim();
+                            let anwser = { #body };
+                            println!("{}", anwser);
+                        }
+                        Err(_) => eprintln!("Server: could not read line"),
                     }
                 }
             }
@@ -78,54 +125,143 @@ impl Server {
         Self::new(&format!("{}", program), deps)
     }
     /// Creates a server out of a Rust module `source`, which is
-    /// expected to implement a `main` function.
-    pub fn new(source: &str, deps: &HashMap<String, DependencySpec>) -> Self {
+    /// expected to implement a `main` function. Returns the errors of
+    /// `cargo` if the server fails to build.
+    pub fn new(source: &str, deps: &HashMap<String, DependencySpec>) -> Result<Self, KrateError> {
         let mut krate = Krate::new();
         krate.add_dependencies(deps);
         krate.source(source);
         krate.use_serde();
-        let mut process = krate.run();
-        let stdout = BufReader::new(process.stdout.take().unwrap());
-        let stderr = BufReader::new(process.stderr.take().unwrap());
-        let stdin = BufWriter::new(process.stdin.take().unwrap());
-        Self {
-            _krate: krate,
+        let binary = match crate::cache::enabled() {
+            true => Self::cached_binary(&krate)?,
+            false => krate.build_release()?,
+        };
+        let (process, stdin, stdout, stderr) = Self::spawn(&binary).map_err(KrateError::Io)?;
+        Ok(Self {
+            process,
             stdout,
-            stdin,
             stderr,
+            stdin,
+            binary,
+            timeout: DEFAULT_TIMEOUT,
+            _krate: krate,
+        })
+    }
+    /// Looks up the binary of `krate` in the cache, and builds and
+    /// caches it otherwise.
+    fn cached_binary(krate: &Krate) -> Result<PathBuf, KrateError> {
+        let binary = krate.cache_key().path("servers");
+        if !binary.exists() {
+            let built = krate.build_release()?;
+            crate::cache::store_file(&binary, &built).map_err(KrateError::Io)?;
         }
+        Ok(binary)
+    }
+    /// Spawns the server `binary`, with threads forwarding its stdout
+    /// and stderr.
+    fn spawn(binary: &Path) -> std::io::Result<Process> {
+        use std::io::Read;
+        let mut process = Command::new(binary)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let (sender, receiver) = mpsc::channel();
+        let stdout = BufReader::new(process.stdout.take().unwrap());
+        std::thread::spawn(move || {
+            for line in stdout.lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let stderr = Arc::new(Mutex::new(String::new()));
+        let mut stderr_pipe = process.stderr.take().unwrap();
+        std::thread::spawn({
+            let stderr = stderr.clone();
+            move || {
+                let mut buffer = [0; 1024];
+                while let Ok(n @ 1..) = stderr_pipe.read(&mut buffer) {
+                    stderr
+                        .lock()
+                        .unwrap()
+                        .push_str(&String::from_utf8_lossy(&buffer[..n]));
+                }
+            }
+        });
+        let stdin = BufWriter::new(process.stdin.take().unwrap());
+        Ok((process, stdin, receiver, stderr))
+    }
+    /// Kills the server and starts it again.
+    fn restart(&mut self) -> Result<(), ServerError> {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+        let (process, stdin, stdout, stderr) =
+            Self::spawn(&self.binary).map_err(|err| ServerError::Restart(err.to_string()))?;
+        self.process = process;
+        self.stdin = stdin;
+        self.stdout = stdout;
+        self.stderr = stderr;
+        Ok(())
     }
     /// Sends a request to the server and blocks until the server
-    /// returns a response.
-    pub fn request(&mut self, req: &str) -> String {
+    /// returns a response, the server crashes, or the timeout is
+    /// reached. On failure, the server is restarted.
+    pub fn try_request(&mut self, req: &str) -> Result<String, ServerError> {
         use std::io::Write;
-        self.stdin.write_all(req.as_bytes()).unwrap();
-        self.stdin.write_all(b"\n").unwrap();
-        self.stdin.flush().unwrap();
-        let mut response = String::new();
-        self.stdout.read_line(&mut response).unwrap();
-        response.trim().into()
+        let sent = self
+            .stdin
+            .write_all(req.as_bytes())
+            .and_then(|_| self.stdin.write_all(b"\n"))
+            .and_then(|_| self.stdin.flush());
+        let response = match sent {
+            Ok(()) => self.stdout.recv_timeout(self.timeout),
+            Err(_) => Err(RecvTimeoutError::Disconnected),
+        };
+        let error = match response {
+            Ok(response) => return Ok(response.trim().into()),
+            Err(RecvTimeoutError::Timeout) => ServerError::Timeout(self.timeout),
+            Err(RecvTimeoutError::Disconnected) => {
+                // Let the stderr thread collect the last words of the server
+                let _ = self.process.wait();
+                std::thread::sleep(Duration::from_millis(50));
+                ServerError::Crashed {
+                    stderr: self.stderr.lock().unwrap().clone(),
+                }
+            }
+        };
+        self.restart()?;
+        Err(error)
     }
-    /// Similar to `request`, but with JSON values.
-    pub fn request_json<T: serde::Serialize, U: serde::de::DeserializeOwned>(
+    /// Similar to `try_request`, but with JSON values.
+    pub fn try_request_json<T: serde::Serialize, U: serde::de::DeserializeOwned>(
         &mut self,
         req: &T,
-    ) -> U {
+    ) -> Result<U, ServerError> {
         let request = serde_json::to_string(req).unwrap();
-        let response = self.request(&request);
-        serde_json::from_str(&response).unwrap_or_else(|err| {
-            eprintln!("ERROR: `server::request_json` failed to parse a value with error `{err:?}`");
-            let request = serde_json::to_string_pretty(req).unwrap();
-            eprintln!("The (pretty printed) request was: <{}>", request);
-            eprintln!("The response is:\n```\n{}\n```>", response);
-            let mut stderr = String::new();
-            self.stderr.read_line(&mut stderr).unwrap();
-            eprintln!("The stderr is:\n```\n{}```\n", stderr);
-            panic!()
+        let response = self.try_request(&request)?;
+        serde_json::from_str(&response).or_else(|err| {
+            let error = ServerError::InvalidResponse {
+                response,
+                stderr: self.stderr.lock().unwrap().clone(),
+                error: err.to_string(),
+            };
+            // The server may be out of sync (e.g. it printed more than
+            // one line), so that its next responses can't be trusted
+            self.restart()?;
+            Err(error)
         })
     }
 }
 
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
 #[test]
 fn server_string() {
     let mut server = Server::from_string_fn(
@@ -133,9 +269,10 @@ fn server_string() {
             request
         },
         &HashMap::new(),
-    );
+    )
+    .unwrap();
     for i in ["A", "B", "C"] {
-        assert_eq!(i, server.request(i));
+        assert_eq!(i, server.try_request(i).unwrap());
     }
 }
 
@@ -147,7 +284,44 @@ fn server_json() {
             x + y
         },
         &HashMap::new(),
-    );
-    assert_eq!(13u16, server.request_json::<_, u16>(&(3u8, 10u8)));
-    assert_eq!(42u16, server.request_json::<_, u16>(&(40u8, 2u8)));
+    )
+    .unwrap();
+    assert_eq!(13u16, server.try_request_json::<_, u16>(&(3u8, 10u8)).unwrap());
+    assert_eq!(42u16, server.try_request_json::<_, u16>(&(40u8, 2u8)).unwrap());
+}
+
+#[test]
+fn server_recovery() {
+    let mut server = Server::from_string_fn(
+        quote! {
+            match request {
+                "loop" => loop {},
+                "abort" => std::process::abort(),
+                _ => request.to_string(),
+            }
+        },
+        &HashMap::new(),
+    )
+    .unwrap();
+    server.timeout = Duration::from_secs(1);
+    assert!(matches!(
+        server.try_request("loop"),
+        Err(ServerError::Timeout(_))
+    ));
+    assert_eq!("A", server.try_request("A").unwrap());
+    assert!(matches!(
+        server.try_request("abort"),
+        Err(ServerError::Crashed { .. })
+    ));
+    assert_eq!("B", server.try_request("B").unwrap());
+}
+
+#[test]
+fn server_invalid_response() {
+    let mut server = Server::from_string_fn(quote! {request.to_string()}, &HashMap::new()).unwrap();
+    assert!(matches!(
+        server.try_request_json::<_, u16>(&"A"),
+        Err(ServerError::InvalidResponse { .. })
+    ));
+    assert_eq!(42u16, server.try_request_json::<_, u16>(&42u16).unwrap());
 }
@@ -1,43 +1,145 @@
 use crate::prelude::*;
 use once_cell::sync::Lazy;
 use std::fs;
-use std::sync::Mutex;
+use std::sync::{Mutex, OnceLock};
 
 #[derive(Copy, Clone, Debug, Default, Hash, Eq, PartialEq)]
-pub(super) struct KrateId(usize);
+pub(super) struct KrateId {
+    job: usize,
+    nth: usize,
+}
 
 impl KrateId {
     pub(super) fn name(&self) -> String {
-        format!("testify_reserved_named_crate_{}", self.0).to_string()
+        format!("testify_reserved_named_crate_{}", self.nth).to_string()
+    }
+
+    /// The job whose workspace contains the crate.
+    pub(super) fn job(&self) -> usize {
+        self.job
     }
 }
 
+/// A scratch workspace. Each job has its own workspace, so that the
+/// manifest of a workspace (and the `Cargo.lock` cargo writes next to
+/// it) are never rewritten while another job runs cargo on it.
 #[derive(Debug)]
 pub struct Workspace {
+    job: usize,
     pub(super) root: PathBuf,
     pub(super) crates: HashSet<KrateId>,
+    /// For crates copied along with their original workspace, the
+    /// path of the crate within its directory.
+    pub(super) subdirs: HashMap<KrateId, PathBuf>,
+    /// The crates that are reserved but not populated yet: they are
+    /// not members of the workspace until they are registered.
+    pending: HashSet<KrateId>,
+    /// An exclusive lock on the slot of the workspace, held for the
+    /// lifetime of the process, so that other testify processes use
+    /// other slots instead of garbage collecting our crates.
+    _lock: fs::File,
+}
+
+static ROOT: OnceLock<PathBuf> = OnceLock::new();
+static TARGET_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Sets the directory in which crates are generated. This has no
+/// effect once a crate was generated. By default, the directory is
+/// given by the environment variable `TESTIFY_WORKSPACE`, or is
+/// `$TMP/testify`.
+pub fn set_root(root: PathBuf) {
+    let _ = ROOT.set(root);
+}
+
+/// Sets the directory where cargo puts build artifacts
+/// (`CARGO_TARGET_DIR`). By default, the directory is given by the
+/// environment variable `TESTIFY_TARGET_DIR`, or is the directory
+/// `target` of the cache (see [`crate::cache::cache_dir`]), so that
+/// dependencies are built only once across runs.
+pub fn set_target_dir(target_dir: PathBuf) {
+    let _ = TARGET_DIR.set(target_dir);
+}
+
+thread_local! {
+    static JOB: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Declares that the current thread runs the job `job`. Jobs build
+/// in distinct target directories, so that they don't wait for each
+/// other on the lock cargo takes on a target directory.
+pub(crate) fn set_job(job: usize) {
+    JOB.set(job)
+}
+
+/// The directory where cargo puts build artifacts for the job of
+/// the current thread.
+pub fn target_dir() -> PathBuf {
+    let target_dir = TARGET_DIR.get_or_init(|| {
+        std::env::var_os("TESTIFY_TARGET_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| crate::cache::cache_dir().join("target"))
+    });
+    match JOB.get() {
+        0 => target_dir.clone(),
+        job => target_dir.join(format!("job-{job}")),
+    }
 }
 
-static WORKSPACE: Lazy<Mutex<Workspace>> = Lazy::new(|| {
-    let root = std::env::temp_dir().join("testify");
-    std::fs::create_dir_all(&root).unwrap();
-    let root = std::fs::canonicalize(&root).unwrap();
-    let workspace = Workspace {
-        root,
-        crates: HashSet::new(),
-    };
-    workspace.collect_garbadge();
-    workspace.write_workspace_manifest();
-    Mutex::new(workspace)
-});
+/// The workspaces of the jobs, created on demand and kept for the
+/// lifetime of the process.
+static WORKSPACES: Lazy<Mutex<HashMap<usize, &'static Mutex<Workspace>>>> =
+    Lazy::new(Default::default);
 
+/// Locks the workspace of the job of the current thread.
 pub(super) fn lock_workspace() -> std::sync::MutexGuard<'static, Workspace> {
-    WORKSPACE
+    lock_job_workspace(JOB.get())
+}
+
+/// Locks the workspace of the job `job`.
+pub(super) fn lock_job_workspace(job: usize) -> std::sync::MutexGuard<'static, Workspace> {
+    let workspace: &'static Mutex<Workspace> = WORKSPACES
         .lock()
-        .expect("lock_workspace: could not lock WORKSPACE")
+        .unwrap()
+        .entry(job)
+        .or_insert_with(|| Box::leak(Box::new(Mutex::new(Workspace::new(job)))));
+    workspace
+        .lock()
+        .expect("lock_workspace: could not lock the workspace")
 }
 
 impl Workspace {
+    fn new(job: usize) -> Self {
+        let root = ROOT.get().cloned().unwrap_or_else(|| {
+            std::env::var_os("TESTIFY_WORKSPACE")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| std::env::temp_dir().join("testify"))
+        });
+        std::fs::create_dir_all(&root).unwrap();
+        // The workspace is the first slot of `root` that no other
+        // workspace (of this process or of another one) holds
+        let (root, lock) = (0..)
+            .find_map(|slot| {
+                let lock = fs::File::create(root.join(format!("slot-{slot}.lock"))).unwrap();
+                lock.try_lock()
+                    .is_ok()
+                    .then(|| (root.join(format!("slot-{slot}")), lock))
+            })
+            .unwrap();
+        std::fs::create_dir_all(&root).unwrap();
+        let root = std::fs::canonicalize(&root).unwrap();
+        let workspace = Workspace {
+            job,
+            root,
+            crates: HashSet::new(),
+            subdirs: HashMap::new(),
+            pending: HashSet::new(),
+            _lock: lock,
+        };
+        workspace.collect_garbadge();
+        workspace.write_workspace_manifest();
+        workspace
+    }
+
     fn write_workspace_manifest(&self) {
         let contents = format!(
             r#"
@@ -46,63 +148,61 @@ resolver = "2"
 members = {:#?}"#,
             self.crates
                 .iter()
-                .map(|krate| krate.name())
+                .filter(|krate| !self.pending.contains(krate))
+                .map(|krate| self
+                    .crate_path(*krate)
+                    .strip_prefix(&self.root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned())
                 .collect::<Vec<_>>()
         );
         fs::write(self.root.join("Cargo.toml"), contents).unwrap();
     }
-    pub(super) fn crate_path(&self, krate_id: KrateId) -> PathBuf {
-        self.root.join(&krate_id.name())
-    }
-    pub(super) fn write_crate_main(&self, krate_id: KrateId, source: &str) {
-        fs::write(self.crate_path(krate_id).join("main.rs"), source).unwrap();
+    /// The directory of a crate, which contains the whole original
+    /// workspace for crates duplicated from a workspace member.
+    fn crate_dir(&self, krate_id: KrateId) -> PathBuf {
+        self.root.join(krate_id.name())
     }
-    pub(super) fn write_crate_manifest(
-        &self,
-        krate_id: KrateId,
-        dependencies: &HashMap<String, DependencySpec>,
-    ) {
-        let name = krate_id.name();
-        let dependencies = dependencies_to_string(dependencies);
-        fs::write(
-            self.crate_path(krate_id).join("Cargo.toml"),
-            format!(
-                r#"[package]
-name = "{name}"
-version = "0.1.0"
-edition = "2021"
-
-[[bin]]
-name = "{name}"
-path = "main.rs"
-
-{dependencies}
-[dependencies.marshalling]
-path = "{}/marshalling"
-"#,
-                std::env!("CARGO_MANIFEST_DIR")
-            ),
-        )
-        .unwrap();
+    fn crate_path(&self, krate_id: KrateId) -> PathBuf {
+        let dir = self.crate_dir(krate_id);
+        match self.subdirs.get(&krate_id) {
+            Some(subdir) => dir.join(subdir),
+            None => dir,
+        }
     }
-    pub(super) fn add_crate(&mut self) -> KrateId {
+    /// Reserves a fresh crate, and returns its (empty)
+    /// directory. The crate is not a member of the workspace until
+    /// it is registered with `register_crate`, so that a crate which
+    /// is being populated never breaks `cargo` invocations on other
+    /// crates.
+    pub(super) fn reserve_crate(&mut self) -> (KrateId, PathBuf) {
         let krate_id = (0..)
-            .map(KrateId)
+            .map(|nth| KrateId { job: self.job, nth })
             .find(|id| !self.crates.contains(id))
             .unwrap();
-
-        fs::create_dir_all(&self.crate_path(krate_id)).unwrap();
-
+        let dir = self.crate_dir(krate_id);
+        fs::create_dir_all(&dir).unwrap();
         self.crates.insert(krate_id);
-
-        self.write_crate_manifest(krate_id, &HashMap::default());
-        self.write_crate_main(krate_id, "fn main() {}");
+        self.pending.insert(krate_id);
+        (krate_id, dir)
+    }
+    /// Makes a reserved crate a member of the workspace. For crates
+    /// copied along with their original workspace, `subdir` is the
+    /// path of the crate within its directory.
+    pub(super) fn register_crate(&mut self, krate_id: KrateId, subdir: Option<PathBuf>) {
+        if let Some(subdir) = subdir {
+            self.subdirs.insert(krate_id, subdir);
+        }
+        self.pending.remove(&krate_id);
         self.write_workspace_manifest();
-
-        krate_id
     }
     pub(super) fn remove_crate(&mut self, krate_id: KrateId) {
         self.crates.remove(&krate_id);
+        self.subdirs.remove(&krate_id);
+        if !self.pending.remove(&krate_id) {
+            self.write_workspace_manifest();
+        }
         self.collect_garbadge();
     }
     fn collect_garbadge(&self) {
@@ -124,3 +224,37 @@ path = "{}/marshalling"
         }
     }
 }
+
+/// Writes the `main.rs` of the generated crate in `dir`.
+pub(super) fn write_crate_main(dir: &Path, source: &str) {
+    fs::write(dir.join("main.rs"), source).unwrap();
+}
+
+/// Writes the manifest of the generated crate `name` in `dir`.
+pub(super) fn write_crate_manifest(
+    dir: &Path,
+    name: &str,
+    dependencies: &HashMap<String, DependencySpec>,
+) {
+    let dependencies = dependencies_to_string(dependencies);
+    fs::write(
+        dir.join("Cargo.toml"),
+        format!(
+            r#"[package]
+name = "{name}"
+version = "0.1.0"
+edition = "2021"
+
+[[bin]]
+name = "{name}"
+path = "main.rs"
+
+{dependencies}
+[dependencies.marshalling]
+path = "{}/marshalling"
+"#,
+            std::env!("CARGO_MANIFEST_DIR")
+        ),
+    )
+    .unwrap();
+}
@@ -1,13 +1,23 @@
 mod subst;
 
+pub mod attributes;
+pub mod cache;
 mod complex_input_value;
+pub mod contracts_file;
 pub mod driver;
+pub mod error;
 mod krate;
 pub mod llm;
 pub mod pool;
 pub mod prelude;
+pub mod summary;
+mod tested_function;
 mod utils;
 
+pub use error::Error;
+pub use krate::{coverage, export, mutation, workspace};
+pub use tested_function::TestedFunction;
+
 use crate::prelude::*;
 
 pub type InputName = String;
@@ -49,9 +59,10 @@ pub struct Input {
 
 /// Represents a span of code or file content. Contains line-column information, byte offset,
 /// and optional file location. Useful for error reporting and diagnostics.
-#[derive(Debug, Clone, Hash)]
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
 pub struct Span {
     /// The starting line and column of this span.
+    #[serde(with = "line_column")]
     pub start: proc_macro2::LineColumn,
     /// The number of bytes this span covers.
     pub bytes: usize,
@@ -59,6 +70,26 @@ pub struct Span {
     pub file: Option<PathBuf>,
 }
 
+/// (De)serializes a `proc_macro2::LineColumn` as a `(line, column)`
+/// pair.
+mod line_column {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        start: &proc_macro2::LineColumn,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        (start.line, start.column).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<proc_macro2::LineColumn, D::Error> {
+        let (line, column) = Deserialize::deserialize(deserializer)?;
+        Ok(proc_macro2::LineColumn { line, column })
+    }
+}
+
 impl Span {
     /// Creates a "dummy" span, with no meaningful location information.
     pub fn dummy() -> Self {
@@ -68,6 +99,41 @@ impl Span {
             file: None,
         }
     }
+
+    /// Creates the span of the bytes `range` of `source`, the
+    /// contents of `file`.
+    pub fn from_range(source: &str, file: Option<PathBuf>, range: std::ops::Range<usize>) -> Self {
+        let before = &source[..range.start];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Self {
+            start: proc_macro2::LineColumn {
+                line: before.matches('\n').count() + 1,
+                column: before[line_start..].chars().count(),
+            },
+            bytes: range.len(),
+            file,
+        }
+    }
+
+    /// Whether the span carries no location information.
+    pub fn is_dummy(&self) -> bool {
+        self.start.line == 0 && self.file.is_none()
+    }
+}
+
+impl std::fmt::Display for Span {
+    /// Formats the span as `file:line:column` (with a 1-based
+    /// column), which editors understand.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.is_dummy() {
+            return write!(f, "<unknown location>");
+        }
+        let file = self
+            .file
+            .as_ref()
+            .map_or("<unknown file>".into(), |file| file.display().to_string());
+        write!(f, "{file}:{}:{}", self.start.line, self.start.column + 1)
+    }
 }
 
 /// A specification of dependencies for a `Contract`, stored as a `toml::Value`. The TOML value is expected to be similar to what Cargo expects for dependencies.
@@ -81,11 +147,30 @@ impl Default for DependencySpec {
     }
 }
 
+impl DependencySpec {
+    /// The path of the dependency, if it is a path dependency.
+    pub fn path(&self) -> Option<PathBuf> {
+        self.0.get("path")?.as_str().map(PathBuf::from)
+    }
+}
+
 /// Default number of tests to run
 fn default_tests_number() -> usize {
     5
 }
 
+/// How values are picked when instantiating the inputs of a contract.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    /// Values are picked uniformly at random.
+    #[default]
+    Random,
+    /// Values are biased toward the boundaries of their types (e.g.
+    /// `0`, `MIN` or `MAX`), half of the time.
+    Boundary,
+}
+
 /// A `Contract` defines a set of inputs, a description, a precondition, and a postcondition.
 /// It can also contain additional data such as dependencies, use-statements, and an optional
 /// tested function. Contracts can be instantiated with concrete inputs and then evaluated.
@@ -96,6 +181,13 @@ pub struct Contract {
     pub inputs: Vec<Input>,
     /// A human-readable description of what the contract represents or enforces.
     pub description: String,
+    /// A short name identifying the contract, e.g. to select it with
+    /// `--filter`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Tags grouping contracts, e.g. to select them with `--tag`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
     /// A precondition expression, which must hold before the code under test is run.
     #[debug("{}", precondition.into_token_stream())]
     #[serde(with = "serde_via::SerdeVia")]
@@ -107,9 +199,12 @@ pub struct Contract {
     #[serde(default = "default_expr")]
     pub postcondition: syn::Expr,
     /// The span of the contract definition in source code, used for diagnostics.
-    #[serde(default = "Span::dummy")]
-    #[serde(with = "serde_via::SerdeVia")]
+    #[serde(default = "Span::dummy", skip_serializing_if = "Span::is_dummy")]
     pub span: Span,
+    /// The spans of the fields of the contract definition, keyed by
+    /// field name.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub field_spans: HashMap<String, Span>,
     /// Dependencies required by this contract, keyed by name.
     #[serde(default)]
     pub dependencies: HashMap<String, DependencySpec>,
@@ -117,15 +212,24 @@ pub struct Contract {
     #[serde(with = "serde_via::SerdeVia")]
     #[serde(default)]
     pub use_statements: Vec<syn::UseTree>,
-    /// The function under test, if any, represented by a `syn::Path`.
+    /// The function under test, if any. This can be a function, a
+    /// method (`Type::method`) or a trait method on a named impl
+    /// (`<Type as Trait>::method`). Generic arguments are ignored.
     #[serde(with = "serde_via::SerdeVia")]
-    pub function_tested: Option<syn::Path>,
+    pub function_tested: Option<syn::ExprPath>,
     /// Seed for randomness
     #[serde(default)]
     pub seed: Option<u64>,
     /// Number of tests to generate. 5 by default.
     #[serde(default = "default_tests_number")]
     pub tests: usize,
+    /// Minimum line coverage (in percent) expected for the tested
+    /// function. Overrides the global `--min-coverage` threshold.
+    #[serde(default)]
+    pub min_coverage: Option<f64>,
+    /// How values are picked for the inputs of this contract.
+    #[serde(default)]
+    pub strategy: Strategy,
 }
 
 impl std::hash::Hash for Contract {
@@ -134,12 +238,24 @@ impl std::hash::Hash for Contract {
         self.description.hash(state);
         self.precondition.hash(state);
         self.postcondition.hash(state);
-        self.span.hash(state);
+        // Seeds don't depend on where the contract is written, nor on
+        // how it is named or tagged. Spans used not to be recorded:
+        // a dummy one is hashed instead, so that default seeds stay
+        // stable
+        Span::dummy().hash(state);
         for elem in &self.dependencies {
             format!("{elem:?}").hash(state);
         }
         self.use_statements.hash(state);
-        self.function_tested.hash(state);
+        // Hashes like a plain `syn::Path` when unqualified, so that
+        // default seeds stay stable
+        self.function_tested
+            .as_ref()
+            .map(|function| &function.path)
+            .hash(state);
+        if let Some(qself) = self.function_tested.as_ref().and_then(|f| f.qself.as_ref()) {
+            qself.hash(state);
+        }
         self.seed.hash(state);
     }
 }
@@ -172,9 +288,9 @@ impl Contract {
     }
 
     /// Normalizes all file paths in the dependencies, converting relative paths to absolute paths
-    /// based on the current working directory.
-    pub fn normalize_paths(&mut self) {
-        let workdir = std::env::current_dir().unwrap();
+    /// based on `base_dir` (typically, the directory of the file declaring the contract).
+    pub fn normalize_paths(&mut self, base_dir: &Path) {
+        let workdir = std::env::current_dir().unwrap().join(base_dir);
         for (_, DependencySpec(toml)) in self.dependencies.iter_mut() {
             let Some(path) = toml.get_mut("path") else {
                 continue;
@@ -182,13 +298,19 @@ impl Contract {
             let Some(str_path) = path.as_str() else {
                 continue;
             };
-            *path = toml::Value::String(
-                workdir
-                    .join(PathBuf::from(str_path))
-                    .into_os_string()
-                    .into_string()
-                    .unwrap(),
-            );
+            // Resolve `.` and `..` so that the same crate declared from
+            // different files yields the same path
+            let mut normalized = PathBuf::new();
+            for component in workdir.join(str_path).components() {
+                match component {
+                    std::path::Component::CurDir => (),
+                    std::path::Component::ParentDir => {
+                        normalized.pop();
+                    }
+                    component => normalized.push(component),
+                }
+            }
+            *path = toml::Value::String(normalized.into_os_string().into_string().unwrap());
         }
     }
 }
@@ -225,18 +347,11 @@ pub struct Precondition {
 }
 
 impl Contract {
-    /// Retrieves the tested function path segments as a vector of strings, if a function is tested.
-    /// Each segment is asserted to have no path arguments.
-    fn function_tested(&self) -> Option<Vec<String>> {
-        Some(
-            self.function_tested
-                .as_ref()?
-                .segments
-                .iter()
-                .inspect(|segment| assert_eq!(segment.arguments, syn::PathArguments::None))
-                .map(|segment| format!("{}", segment.ident))
-                .collect(),
-        )
+    /// Retrieves the normalized path of the tested function, if a function is tested and its
+    /// path is supported (see `TestedFunction::new`). The driver rejects unsupported paths
+    /// beforehand.
+    fn function_tested(&self) -> Option<TestedFunction> {
+        TestedFunction::new(self.function_tested.as_ref()?).ok()
     }
 
     /// Removes and returns the specified input from the contract, along with an iterator over the
@@ -256,13 +371,13 @@ impl Contract {
     }
 
     /// Returns a set of concrete identifiers found in both the precondition and postcondition
-    /// expressions, excluding those declared as inputs. This set can help identify free variables
-    /// that must be handled or instantiated.
+    /// expressions, excluding those bound by patterns (see `IdentCollector`). Inputs are not
+    /// excluded: this set can help identify free variables that must be handled or instantiated.
     pub fn concrete_idents(&self) -> HashSet<syn::Ident> {
         let mut visitor = IdentCollector::default();
         visitor.visit_expr(&self.precondition);
         visitor.visit_expr(&self.postcondition);
-        visitor.idents()
+        visitor.free_idents()
     }
 
     /// Substitutes all occurrences of the given input name in both the precondition and postcondition
@@ -354,13 +469,26 @@ impl Contract {
     }
 
     /// Extracts `eval` nodes from the precondition and postcondition by partially computing certain
-    /// parts of the expressions. Returns a vector of `(String, TokenStream)` pairs representing the
-    /// extracted nodes.
-    pub fn extract_eval_nodes(&mut self) -> Vec<(String, proc_macro2::TokenStream)> {
+    /// parts of the expressions. Returns a vector of `(field, String, TokenStream)` triples
+    /// representing the extracted nodes, along with the field (`"precondition"` or
+    /// `"postcondition"`) they were extracted from.
+    pub fn extract_eval_nodes(&mut self) -> Vec<(&'static str, String, proc_macro2::TokenStream)> {
         let mut visitor = PartialCompute::new();
         visitor.visit_expr_mut(&mut self.postcondition);
+        let in_postcondition = visitor.node_count();
         visitor.visit_expr_mut(&mut self.precondition);
-        visitor.get_nodes()
+        visitor
+            .get_nodes()
+            .into_iter()
+            .enumerate()
+            .map(|(nth, (identifier, node))| {
+                let field = match nth < in_postcondition {
+                    true => "postcondition",
+                    false => "precondition",
+                };
+                (field, identifier, node)
+            })
+            .collect()
     }
 
     /// Substitutes identifiers within the pre- and postcondition expressions with the given map of
@@ -423,17 +551,23 @@ fn concretization() {
         }},
         postcondition: syn::parse_quote! {x + 1 == eval(x + 1)},
         span: Span::dummy(),
+        field_spans: HashMap::new(),
+        name: None,
+        tags: vec![],
         dependencies: HashMap::new(),
         function_tested: None,
         use_statements: vec![],
         seed: None,
+        tests: 5,
+        min_coverage: None,
+        strategy: Strategy::Random,
     };
 
     for pool in pool::ContractPool::new_pools(vec![contract]) {
-        let pool = pool.instantiate_types();
-        let mut pool = pool.instantiate_values();
+        let pool = pool.instantiate_types().unwrap();
+        let mut pool = pool.instantiate_values().unwrap();
         println!("contracts = {:#?}", pool.contracts());
-        pool.compute_eval_nodes();
+        pool.compute_eval_nodes().unwrap();
         println!("evaluated contracts = {:#?}", pool.contracts());
     }
     // println!("A: {:#?}", contract);
@@ -446,6 +580,25 @@ fn concretization() {
     // println!("{:#?}", contracts);
 }
 
+#[test]
+fn eval_node_fields() {
+    let mut contract: Contract = toml::from_str(
+        r#"
+        description = ""
+        precondition = "x > eval(1 + 1)"
+        postcondition = "eval(2 * 3) == x && eval(4) > 0"
+        function_tested = "krate::f"
+        "#,
+    )
+    .unwrap();
+    let fields: Vec<_> = contract
+        .extract_eval_nodes()
+        .into_iter()
+        .map(|(field, _, _)| field)
+        .collect();
+    assert_eq!(fields, ["postcondition", "postcondition", "precondition"]);
+}
+
 trait PrependLocal {
     fn prepend_local(&mut self, local: syn::Local);
     fn prepend_binding(&mut self, span: proc_macro2::Span, lhs: syn::Pat, rhs: syn::Expr) {
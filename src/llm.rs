@@ -26,11 +26,16 @@ impl Contract {
             precondition: parse_quote! {true},
             postcondition: parse_quote! {true},
             span: Span::dummy(),
+            field_spans: HashMap::new(),
+            name: None,
+            tags: vec![],
             dependencies: HashMap::new(),
             use_statements: vec![],
             function_tested: None,
             seed: None,
             tests: default_tests_number(),
+            min_coverage: None,
+            strategy: Strategy::default(),
         }
     }
 }
@@ -207,16 +212,18 @@ impl PromptContext {
     }
 
     /// Constructs a new `PromptContext` using the provided dependencies, item to test, and contracts.
+    /// Fails if the path of the item is not supported, if its crate cannot be duplicated, or if
+    /// hax fails on it.
     pub fn new(
         dependencies: &HashMap<String, DependencySpec>,
-        item_to_test: syn::Path,
+        item_to_test: syn::ExprPath,
         contracts: &Vec<Contract>,
-    ) -> PromptContext {
+    ) -> Result<PromptContext, crate::Error> {
+        let function_tested = crate::TestedFunction::new(&item_to_test)?;
         let mut contract = Contract::dummy();
         contract.dependencies = dependencies.clone();
         contract.function_tested = Some(item_to_test);
-        let function_tested = contract.function_tested().unwrap();
-        let krate_name = &function_tested[0];
+        let krate_name = function_tested.krate();
         let krate = {
             // Find the full path to the source of the crate `krate_name`.
             let krate_path = {
@@ -239,11 +246,11 @@ impl PromptContext {
             Krate::duplicate_crate(
                 &krate_path,
                 &contract.dependencies.clone().into_iter().collect(),
-            )
-            .unwrap()
+            )?
         };
 
-        let items = krate.hax().unwrap();
+        let items = krate.hax().map_err(|error| error.into_error(vec![], &[]))?;
+        let nested_items: Vec<_> = items.iter().flat_map(|item| item.nested_items()).collect();
         let def_id_to_string = |did: &hax_frontend_exporter::DefId| {
             let mut did = (&did as &hax_frontend_exporter::DefIdContents).clone();
             if did.krate == krate.name() {
@@ -252,27 +259,27 @@ impl PromptContext {
             did.into_string()
         };
         let find_item = |fn_path: String| {
-            items
+            nested_items
                 .iter()
                 .find(|item| def_id_to_string(&item.owner_id) == fn_path)
         };
-        let function_tested = function_tested.join("::");
+        let workdir = krate.workspace_path();
         trace!("function_tested={function_tested}");
         trace!(
             "items={:#?}",
-            items
+            nested_items
                 .iter()
                 .map(|item| def_id_to_string(&item.owner_id))
                 .collect::<Vec<_>>()
         );
-        let item = find_item(function_tested.clone()).unwrap();
-        let workdir = krate.workspace_path();
+        let item = function_tested.resolve(&items, &workdir).unwrap();
+        let item_id = def_id_to_string(&item.owner_id);
         let related_items_ids = item.def_ids();
         let related_items_contents: Vec<String> = related_items_ids
             .iter()
             .map(def_id_to_string)
             .flat_map(find_item)
-            .filter(|i| def_id_to_string(&i.owner_id) != function_tested)
+            .filter(|i| def_id_to_string(&i.owner_id) != item_id)
             .map(|i| i.span.clone())
             .unique()
             .flat_map(|span| span.source(&workdir))
@@ -284,20 +291,21 @@ impl PromptContext {
         let related_contracts = contracts
             .into_iter()
             .filter(|c| {
-                let id = c
-                    .function_tested()
-                    .map(|path| path.join("::").to_string())
-                    .unwrap_or("!".to_string());
-                related_items_ids.contains(&id)
+                c.function_tested()
+                    .filter(|tested| tested.krate() == krate_name)
+                    .and_then(|tested| tested.resolve(&items, &workdir))
+                    .is_some_and(|item| {
+                        related_items_ids.contains(&def_id_to_string(&item.owner_id))
+                    })
             })
             .cloned()
             .collect();
 
-        PromptContext {
-            tested_item: function_tested,
+        Ok(PromptContext {
+            tested_item: function_tested.to_string(),
             tested_item_contents,
             related_items_contents,
             related_contracts,
-        }
+        })
     }
 }
@@ -1,51 +1,252 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use std::fs;
 use std::path::PathBuf;
+use std::sync::Arc;
+use testify::contracts_file::ContractsFile;
 use testify::prelude::*;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
-    contracts: PathBuf,
+    /// The TOML file declaring the contracts, or a directory of contracts files (e.g. a crate)
+    contracts: Option<PathBuf>,
+
+    /// Don't read nor write the persistent cache
+    #[arg(long, global = true)]
+    no_cache: bool,
+
+    /// The directory in which crates are generated [env: TESTIFY_WORKSPACE] [default: $TMP/testify]
+    #[arg(long, global = true, value_name = "DIR")]
+    workspace: Option<PathBuf>,
+
+    /// The directory for build artifacts of generated crates [env: TESTIFY_TARGET_DIR] [default: <cache>/target]
+    #[arg(long, global = true, value_name = "DIR")]
+    target_dir: Option<PathBuf>,
 
     #[command(subcommand)]
     command: Command,
 }
 
+/// Selects the contracts to process.
+#[derive(Args)]
+struct FilterArgs {
+    /// Only process the contracts whose name (or description, when unnamed) matches GLOB
+    #[arg(long, value_name = "GLOB")]
+    filter: Option<String>,
+    /// Only process the contracts tagged with TAG (can be repeated)
+    #[arg(long = "tag", value_name = "TAG")]
+    tags: Vec<String>,
+    /// Only process the contracts testing the function PATH (can be repeated)
+    #[arg(long = "function", value_name = "PATH")]
+    functions: Vec<String>,
+}
+
+impl FilterArgs {
+    fn filter(&self) -> testify::driver::Filter {
+        testify::driver::Filter {
+            name: self.filter.clone(),
+            tags: self.tags.clone(),
+            functions: self
+                .functions
+                .iter()
+                .map(|function| {
+                    let path = syn::parse_str(function).unwrap_or_else(|err| {
+                        eprintln!("{}", format!("Invalid path `{function}`: {err}").red());
+                        std::process::exit(2);
+                    });
+                    if let Err(err) = testify::TestedFunction::new(&path) {
+                        eprintln!("{}", err.to_string().red());
+                        std::process::exit(2);
+                    }
+                    path
+                })
+                .collect(),
+        }
+    }
+}
+
 #[derive(Subcommand)]
 enum Command {
     /// Generates tests and check coverage
     Generate {
         output: PathBuf,
+        #[command(flatten)]
+        filter: FilterArgs,
         /// Check for coverage of tests
         #[clap(long, short, action)]
         coverage: bool,
+        /// Fail if a tested function has less than PERCENT of its lines covered (implies `--coverage`)
+        #[arg(long, value_name = "PERCENT")]
+        min_coverage: Option<f64>,
+        /// The tool used to compute coverage
+        #[arg(long, value_enum, default_value_t = CoverageBackend::Tarpaulin)]
+        coverage_backend: CoverageBackend,
+        /// Generate inputs guided by coverage: keep drawing inputs (for
+        /// at most BUDGET rounds) until tested functions are fully
+        /// covered, and only keep a minimal covering set of tests
+        #[arg(long, value_name = "BUDGET")]
+        coverage_guided: Option<usize>,
+        /// Write the coverage of tested functions as an lcov tracefile (implies `--coverage`)
+        #[arg(long, value_name = "PATH")]
+        lcov: Option<PathBuf>,
+        /// Write the coverage of tested functions as a static HTML page (implies `--coverage`)
+        #[arg(long, value_name = "PATH")]
+        html: Option<PathBuf>,
+        /// Run mutation testing on tested functions, reporting the mutants that the tests don't detect
+        #[arg(long)]
+        mutation: bool,
+        /// Give up on a mutant whose tests run for more than SECONDS
+        #[arg(long, value_name = "SECONDS", default_value_t = 30)]
+        mutation_timeout: u64,
+        /// Process up to N independent pools of contracts concurrently
+        #[arg(long, short, value_name = "N", default_value_t = 1)]
+        jobs: usize,
+    },
+    /// Lists the public functions of crates, with their contracts and coverage
+    CoverageSummary {
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// The crates to summarize (by default, the crates of the tested functions)
+        #[arg(long = "crate", value_name = "NAME")]
+        krates: Vec<String>,
+        /// Print the summary as JSON instead of a table
+        #[arg(long)]
+        json: bool,
+        /// The tool used to compute coverage
+        #[arg(long, value_enum, default_value_t = CoverageBackend::Tarpaulin)]
+        coverage_backend: CoverageBackend,
     },
     /// Auto complete empty contracts
     Auto {
         #[arg(long)]
         ollama: bool,
     },
+    /// Checks the contracts without generating tests: reports syntax
+    /// errors, free identifiers and type errors
+    Check,
+    /// Lists the contracts, with the pool they are assigned to and
+    /// the function they test
+    List {
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Manages the persistent cache
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
 }
 
-#[derive(fmt_derive::Debug, Clone, Serialize, Deserialize)]
-struct ContractsFile {
-    contracts: Vec<Contract>,
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Removes every entry of the cache
+    Clean,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum CoverageBackend {
+    /// Line coverage with `cargo-tarpaulin`
+    Tarpaulin,
+    /// Source-based (region and branch) coverage with `cargo-llvm-cov`
+    LlvmCov,
+}
+
+impl CoverageBackend {
+    fn backend(self) -> Arc<dyn testify::coverage::CoverageBackend> {
+        match self {
+            Self::Tarpaulin => Arc::new(testify::coverage::Tarpaulin),
+            Self::LlvmCov => Arc::new(testify::coverage::LlvmCov),
+        }
+    }
 }
 
 fn main() {
     testify::driver::setup_tracing();
 
     let cli = Cli::parse();
-    let ContractsFile { mut contracts } =
-        toml::from_str(&fs::read_to_string(&cli.contracts).unwrap()).unwrap();
+    if cli.no_cache {
+        testify::cache::disable();
+    }
+    if let Some(workspace) = &cli.workspace {
+        testify::workspace::set_root(workspace.clone());
+    }
+    if let Some(target_dir) = &cli.target_dir {
+        testify::workspace::set_target_dir(target_dir.clone());
+    }
 
-    contracts.iter_mut().for_each(|c| c.normalize_paths());
+    if let Command::Cache {
+        command: CacheCommand::Clean,
+    } = &cli.command
+    {
+        testify::cache::clean().expect("Could not clean the cache");
+        println!("Removed {}", testify::cache::cache_dir().display());
+        return;
+    }
 
-    match &cli.command {
-        Command::Generate { output, coverage } => {
-            testify::driver::run(contracts, output, *coverage)
+    let Some(contracts_path) = &cli.contracts else {
+        eprintln!("{}", "A contracts file is required by this command".red());
+        std::process::exit(2);
+    };
+    if let Command::Check = &cli.command {
+        let ok = testify::driver::check(contracts_path);
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+
+    let mut contracts = match testify::contracts_file::read(contracts_path) {
+        Ok(contracts) => contracts,
+        Err(problems) => {
+            for problem in problems {
+                eprintln!("{}", problem.to_string().red());
+            }
+            std::process::exit(1);
         }
+    };
+
+    if let Command::Generate { filter, .. }
+    | Command::CoverageSummary { filter, .. }
+    | Command::List { filter } = &cli.command
+    {
+        contracts = filter.filter().apply(contracts);
+    }
+
+    let result = match &cli.command {
+        Command::Generate {
+            output,
+            coverage,
+            min_coverage,
+            coverage_backend,
+            coverage_guided,
+            lcov,
+            html,
+            mutation,
+            mutation_timeout,
+            jobs,
+            ..
+        } => testify::driver::run_with_options(
+            contracts,
+            output,
+            &testify::driver::Options {
+                coverage: *coverage || min_coverage.is_some() || lcov.is_some() || html.is_some(),
+                coverage_backend: coverage_backend.backend(),
+                min_coverage: *min_coverage,
+                coverage_guided: *coverage_guided,
+                lcov: lcov.clone(),
+                html: html.clone(),
+                mutation: mutation.then_some(std::time::Duration::from_secs(*mutation_timeout)),
+                jobs: *jobs,
+            },
+        ),
+        Command::CoverageSummary {
+            krates,
+            json,
+            coverage_backend,
+            ..
+        } => testify::driver::coverage_summary(
+            contracts,
+            krates,
+            coverage_backend.backend().as_ref(),
+            *json,
+        ),
         Command::Auto { ollama } => {
             if *ollama {
                 let mut llm = testify::llm::LLM.lock().unwrap();
@@ -59,32 +260,60 @@ fn main() {
                 .cloned()
                 .filter(|c| !c.is_default())
                 .collect();
-            let contracts: Vec<_> = contracts
-                .iter()
-                .cloned()
-                .map(|mut contract| {
-                    if let (true, Some(target)) = (contract.is_default(), &contract.function_tested)
-                    {
-                        let ctx = testify::llm::PromptContext::new(
-                            &contract.dependencies,
-                            target.clone(),
-                            &real_contracts,
-                        );
-                        let testify::llm::PromptResult {
-                            inputs,
-                            postcondition,
-                            precondition,
-                        } = ctx.ask();
-
-                        contract.inputs = inputs;
-                        contract.precondition = precondition;
-                        contract.postcondition = postcondition;
-                    };
-                    contract
+            // Only the contracts of the file itself are completed, the
+            // included ones are used as examples
+            if contracts_path.is_dir() {
+                eprintln!(
+                    "{}",
+                    "`auto` expects a contracts file, not a directory".red()
+                );
+                std::process::exit(2);
+            }
+            let source = fs::read_to_string(contracts_path).unwrap();
+            let ContractsFile { contracts, .. } =
+                testify::contracts_file::parse(&source, Some(contracts_path)).unwrap();
+            let base_dir = contracts_path.parent().unwrap_or(Path::new(""));
+            let completed: Result<Vec<_>, _> = contracts
+                .into_iter()
+                .enumerate()
+                .filter_map(|(nth, mut contract)| {
+                    contract.normalize_paths(base_dir);
+                    let target = contract.function_tested.clone()?;
+                    contract.is_default().then_some((nth, contract, target))
+                })
+                .map(|(nth, mut contract, target)| {
+                    let ctx = testify::llm::PromptContext::new(
+                        &contract.dependencies,
+                        target,
+                        &real_contracts,
+                    )?;
+                    let testify::llm::PromptResult {
+                        inputs,
+                        postcondition,
+                        precondition,
+                    } = ctx.ask();
+
+                    contract.inputs = inputs;
+                    contract.precondition = precondition;
+                    contract.postcondition = postcondition;
+                    Ok((nth, contract))
                 })
                 .collect();
-            let contracts = ContractsFile { contracts };
-            fs::write(&cli.contracts, toml::to_string(&contracts).unwrap()).unwrap();
+            completed.map(|completed| {
+                // Only the completed fields are updated, so that the rest
+                // of the file (comments, `[defaults]`...) is kept as is
+                let source = testify::contracts_file::complete(&source, &completed).unwrap();
+                fs::write(contracts_path, source).unwrap();
+            })
+        }
+        Command::List { .. } => {
+            testify::driver::list(&contracts);
+            Ok(())
         }
+        Command::Check | Command::Cache { .. } => unreachable!(),
+    };
+    if let Err(err) = result {
+        eprintln!("{}", err.to_string().red());
+        std::process::exit(1);
     }
 }
@@ -1,10 +1,13 @@
 use crate::krate::{
+    coverage::{CoverageBackend, CoveragePoint},
+    diagnostics::{mark, KrateError},
+    harness::Harness,
     run_or_locate_error,
-    server::{declare, Server},
+    server::{declare, Server, ServerError},
     Krate,
 };
 use crate::prelude::*;
-use crate::Contract;
+use crate::{Contract, TestedFunction};
 use hax_frontend_exporter::{Ty, TyKind};
 
 /// Declares the types that represent every possible state a pool of
@@ -28,32 +31,59 @@ mod state {
             #[derive(Clone, Debug, ::serde::Serialize, ::serde::Deserialize)]
             pub struct Input {
                 pub id: usize,
-                pub contents: ::serde_json::Value,
+                /// A batch of candidate inputs, each being a JSON array
+                pub batch: Vec<::serde_json::Value>,
             }
-            pub type Output = Option<bool>;
+            /// For each candidate of the batch, the result of the
+            /// precondition, or `None` if it panicked
+            pub type Output = Vec<Option<bool>>;
         }
     }
 
     impl ParametricContracts {
-        /// Test the precondition of the nth contract given
-        /// JSON-encoded inputs. Returns `Some(r)` with `r` the result
-        /// of the precondition, or `None` if compiling or executing
-        /// the precondition panicked.
-        pub fn test_precondition(
+        /// Test the precondition of the nth contract on a batch of
+        /// JSON-encoded inputs, in a single request. For each inputs,
+        /// returns `Ok(Some(r))` with `r` the result of the
+        /// precondition, `Ok(None)` if compiling or executing the
+        /// precondition panicked, or an error if the precondition
+        /// diverged (it timed out or crashed the server) or if the
+        /// server misbehaved. When the batch fails, its inputs are
+        /// tested one by one to find out which ones diverge.
+        pub fn test_preconditions(
             &mut self,
             nth: usize,
-            inputs: Vec<serde_json::Value>,
-        ) -> api::Output {
-            let contents = serde_json::Value::Array(inputs);
-            self.precondition_server
-                .request_json(&api::Input { id: nth, contents })
+            batch: Vec<Vec<serde_json::Value>>,
+        ) -> Vec<Result<Option<bool>, ServerError>> {
+            let mut request = |batch: Vec<Vec<serde_json::Value>>| {
+                let batch = batch.into_iter().map(serde_json::Value::Array).collect();
+                self.precondition_server
+                    .try_request_json::<_, api::Output>(&api::Input { id: nth, batch })
+            };
+            if batch.len() > 1 {
+                if let Ok(results) = request(batch.clone()) {
+                    return results.into_iter().map(Ok).collect();
+                }
+            }
+            batch
+                .into_iter()
+                .map(|inputs| request(vec![inputs]).map(|results| results[0]))
+                .collect()
         }
 
         /// Create a `ParametricContracts` structs: this uses hax to
         /// resolve the input types of the contracts, and sets up a
-        /// precondition server.
-        pub fn new(contracts: &[Contract], deps: &HashMap<String, DependencySpec>) -> Self {
-            assert!(contracts.iter().all(Self::check));
+        /// precondition server. Fails if a contract is generic, if hax
+        /// fails on the input types, or if a precondition doesn't
+        /// compile.
+        pub fn new(
+            contracts: &[Contract],
+            deps: &HashMap<String, DependencySpec>,
+        ) -> Result<Self, crate::Error> {
+            if let Some(contract) = contracts.iter().find(|contract| !Self::check(contract)) {
+                return Err(crate::Error::Generic {
+                    contract: contract.into(),
+                });
+            }
 
             let types = {
                 let queries: Vec<Vec<_>> = contracts
@@ -73,13 +103,27 @@ mod state {
                     .collect();
 
                 let raw_types = execute_hax_queries(
-                    &queries.into_iter().flatten().collect::<Vec<_>>()[..],
+                    &queries.iter().flatten().cloned().collect::<Vec<_>>()[..],
                     deps,
                 )
-                .unwrap_or_else(|e| {
-                    eprintln!("{}", e);
-                    panic!()
-                });
+                .map_err(
+                    |HaxQueryError::HaxError {
+                         queries: failed,
+                         error,
+                     }| {
+                        error.into_error(
+                            contracts
+                                .iter()
+                                .zip(&queries)
+                                .filter(|(_, queries)| {
+                                    queries.iter().any(|query| failed.contains(query))
+                                })
+                                .map(|(contract, _)| contract.into())
+                                .collect(),
+                            &[],
+                        )
+                    },
+                )?;
                 let mut i = 0;
                 let mut types = vec![];
                 for contract in contracts {
@@ -96,51 +140,70 @@ mod state {
                 types
             };
 
-            let precondition_server = {
-                let arms = contracts.iter().enumerate().map(|(i, contract)| {
-                    let pre = &contract.precondition().unwrap();
-                    let predicate = &pre.predicate;
-                    let types = &pre.inputs.iter().map(|(_, typ)| typ).collect::<Vec<_>>();
-                    let names = &pre.inputs.iter().map(|(name, _)| name).collect::<Vec<_>>();
-                    let use_statements = &contract.use_statements;
-                    quote! {
-                        #i => {
-                            let arena = ::marshalling::Arena::new();
-                            use ::marshalling::FromValueRepr as _;
-                            type INPUTS = (#(#types,)*);
-                            let serde_json::Value::Array(vec) = request else {panic!("Expected a JSON array")};
-                            let [#(#names,)*] = &vec[..] else {panic!("Bad number of inputs")};
-                            let (#(#names,)*): INPUTS = (#(<#types>::from_value_repr(&#names, &arena),)*);
-                            let response: api::Output = ::std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                                #(use #use_statements;)*
-                                #predicate
-                            })).ok();
-                            response
-                        }
-                    }
-                });
-
-                Server::from_json_fn(
-                    quote! {
-                        #Api
-                        let api::Input {id, contents} = request;
-                        let request = contents;
-                        #[allow(warning, unused)]
-                        {
-                            match id {
-                                #(#arms)*
-                                _ => panic!(),
-                            }
-                        }
-                    },
-                    deps,
+            // Locate the contracts whose precondition doesn't compile
+            let indexes: Vec<usize> = (0..contracts.len()).collect();
+            let precondition_server = run_or_locate_error(&indexes, |indexes| {
+                Self::precondition_server(contracts, indexes, deps)
+            })
+            .map_err(|(failed, error)| {
+                error.into_error(
+                    failed.iter().map(|i| (&contracts[**i]).into()).collect(),
+                    &contracts.iter().collect::<Vec<_>>(),
                 )
-            };
+            })?;
 
-            Self {
+            Ok(Self {
                 precondition_server,
                 types,
-            }
+            })
+        }
+
+        /// Builds a server testing the preconditions of the contracts
+        /// `contracts[i]` for each `i` in `indexes`, or returns the
+        /// errors of `cargo`.
+        fn precondition_server(
+            contracts: &[Contract],
+            indexes: &[usize],
+            deps: &HashMap<String, DependencySpec>,
+        ) -> Result<Server, KrateError> {
+            let arms = indexes.iter().map(|&i| {
+                let contract = &contracts[i];
+                let pre = &contract.precondition().unwrap();
+                let predicate = mark(i, "precondition", &pre.predicate);
+                let types = &pre.inputs.iter().map(|(_, typ)| typ).collect::<Vec<_>>();
+                let names = &pre.inputs.iter().map(|(name, _)| name).collect::<Vec<_>>();
+                let use_statements = &contract.use_statements;
+                quote! {
+                    #i => batch.into_iter().map(|request| {
+                        let arena = ::marshalling::Arena::new();
+                        use ::marshalling::FromValueRepr as _;
+                        type INPUTS = (#(#types,)*);
+                        let serde_json::Value::Array(vec) = request else {panic!("Expected a JSON array")};
+                        let [#(#names,)*] = &vec[..] else {panic!("Bad number of inputs")};
+                        let (#(#names,)*): INPUTS = (#(<#types>::from_value_repr(&#names, &arena),)*);
+                        let response: Option<bool> = ::std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                            #(use #use_statements;)*
+                            #predicate
+                        })).ok();
+                        response
+                    }).collect::<api::Output>(),
+                }
+            });
+
+            Server::from_json_fn(
+                quote! {
+                    #Api
+                    let api::Input {id, batch} = request;
+                    #[allow(warning, unused)]
+                    {
+                        match id {
+                            #(#arms)*
+                            _ => panic!(),
+                        }
+                    }
+                },
+                deps,
+            )
         }
     }
 
@@ -228,33 +291,64 @@ declare! {
     }
 }
 
+/// Groups contracts with compatible dependencies, returning the
+/// indexes of the contracts of each group.
+pub fn group_by_dependencies(contracts: &[crate::Contract]) -> Vec<Vec<usize>> {
+    let mut groups: Vec<(crate::Contract, Vec<usize>)> = vec![];
+    for (i, contract) in contracts.iter().enumerate() {
+        if let Some((repr, group)) = groups
+            .iter_mut()
+            .find(|(candidate, _)| candidate.dependencies_compatible_with(contract))
+        {
+            let dependencies: HashMap<_, _> = contract
+                .dependencies
+                .iter()
+                .map(|(name, version)| (name.clone(), version.clone()))
+                .chain(repr.dependencies.drain())
+                .collect();
+            repr.dependencies = dependencies.clone();
+            group.push(i);
+        } else {
+            groups.push((contract.clone(), vec![i]));
+        }
+    }
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+/// The maximal number of candidate inputs sent to the precondition
+/// server in a single request.
+const PRECONDITION_BATCH: usize = 64;
+
 impl ContractPool<GenericContracts> {
     /// Creates a fresh pool
     pub fn new_pools(contracts: Vec<crate::Contract>) -> Vec<Self> {
-        let mut groups: Vec<(crate::Contract, Vec<crate::Contract>)> = vec![];
-        for contract in contracts {
-            if let Some((repr, group)) = groups
-                .iter_mut()
-                .find(|(candidate, _)| candidate.dependencies_compatible_with(&contract))
-            {
-                let dependencies: HashMap<_, _> = contract
-                    .dependencies
-                    .iter()
-                    .map(|(name, version)| (name.clone(), version.clone()))
-                    .chain(repr.dependencies.drain())
-                    .collect();
-                repr.dependencies = dependencies.clone();
-                group.push(contract);
-            } else {
-                groups.push((contract.clone(), vec![contract]));
-            }
-        }
+        let groups = group_by_dependencies(&contracts);
+        let mut contracts: Vec<_> = contracts.into_iter().map(Some).collect();
         groups
             .into_iter()
-            .map(|(_, group)| Self::new(group))
+            .map(|group| {
+                Self::new(
+                    group
+                        .into_iter()
+                        .map(|i| contracts[i].take().unwrap())
+                        .collect(),
+                )
+            })
             .collect()
     }
 
+    /// Serializes the contracts of the pool. Contracts hold `syn`
+    /// values, which cannot be sent to another thread: a pool is sent
+    /// as JSON instead.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.contracts).unwrap()
+    }
+
+    /// Deserializes a pool serialized with `to_json`.
+    pub fn from_json(json: &str) -> Self {
+        Self::new(serde_json::from_str(json).unwrap())
+    }
+
     /// Creates a fresh pool, assuming all contracts have compatible dependencies
     fn new(contracts: Vec<crate::Contract>) -> Self {
         Self {
@@ -263,10 +357,12 @@ impl ContractPool<GenericContracts> {
         }
     }
 
-    pub fn instantiate_types(self) -> ContractPool<ParametricContracts> {
-        let state = ParametricContracts::new(&self.contracts, &self.dependencies());
-        self.retype(state)
-            .expect("Generic contracts are not supported yet")
+    pub fn instantiate_types(self) -> Result<ContractPool<ParametricContracts>, crate::Error> {
+        let state = ParametricContracts::new(&self.contracts, &self.dependencies())?;
+        Ok(ContractPool {
+            contracts: self.contracts,
+            state,
+        })
     }
 }
 
@@ -282,9 +378,19 @@ pub fn arbitrary_with_seed<T: for<'a> arbitrary::Arbitrary<'a>>(seed: u64) -> T
     T::arbitrary(&mut unstructured).unwrap()
 }
 
+/// With the `Boundary` strategy, decides whether the value drawn for
+/// `seed` is one of the boundaries of its type (half of the time), and
+/// which one. The decision is drawn from a stream independent of the
+/// one of `seed`, so that it doesn't bias the values drawn otherwise.
+fn boundary_pick(seed: u64) -> Option<usize> {
+    const STREAM: u64 = 0x9e37_79b9_7f4a_7c15;
+    let roll = arbitrary_with_seed::<u8>(seed.wrapping_mul(STREAM) ^ STREAM);
+    (roll % 2 == 0).then_some(roll as usize / 2)
+}
+
 use hax_frontend_exporter::{IntTy, UintTy};
 // This should generate an AST
-fn generate_for_type(seed: u64, ty: &Ty) -> (serde_json::Value, String) {
+fn generate_for_type(seed: u64, ty: &Ty, strategy: Strategy) -> (serde_json::Value, String) {
     use marshalling::*;
     fn rand<T: ToValueRepr + ToRustExpr + for<'a> arbitrary::Arbitrary<'a>>(
         seed: u64,
@@ -294,19 +400,44 @@ fn generate_for_type(seed: u64, ty: &Ty) -> (serde_json::Value, String) {
         let expr = value.to_rust_expr();
         (repr, expr)
     }
+    // With the `Boundary` strategy, half of the values are picked
+    // among the boundaries of the type.
+    let boundary = match strategy {
+        Strategy::Random => None,
+        Strategy::Boundary => boundary_pick(seed),
+    };
+    macro_rules! pick {
+        ($t:ty) => {
+            match boundary {
+                Some(nth) => {
+                    let boundaries: [$t; 6] = [
+                        <$t>::MIN,
+                        <$t>::MIN.wrapping_add(1),
+                        0,
+                        1,
+                        <$t>::MAX.wrapping_sub(1),
+                        <$t>::MAX,
+                    ];
+                    let value = boundaries[nth % boundaries.len()];
+                    (value.to_value_repr(), value.to_rust_expr())
+                }
+                None => rand::<$t>(seed),
+            }
+        };
+    }
     match ty.kind() {
-        TyKind::Uint(UintTy::U8) => rand::<u8>(seed),
-        TyKind::Uint(UintTy::U16) => rand::<u16>(seed),
-        TyKind::Uint(UintTy::U32) => rand::<u32>(seed),
-        TyKind::Uint(UintTy::U64) => rand::<u64>(seed),
-        TyKind::Uint(UintTy::U128) => rand::<u128>(seed),
-        TyKind::Uint(UintTy::Usize) => rand::<usize>(seed),
-        TyKind::Int(IntTy::I8) => rand::<i8>(seed),
-        TyKind::Int(IntTy::I16) => rand::<i16>(seed),
-        TyKind::Int(IntTy::I32) => rand::<i32>(seed),
-        TyKind::Int(IntTy::I64) => rand::<i64>(seed),
-        TyKind::Int(IntTy::I128) => rand::<i128>(seed),
-        TyKind::Int(IntTy::Isize) => rand::<isize>(seed),
+        TyKind::Uint(UintTy::U8) => pick!(u8),
+        TyKind::Uint(UintTy::U16) => pick!(u16),
+        TyKind::Uint(UintTy::U32) => pick!(u32),
+        TyKind::Uint(UintTy::U64) => pick!(u64),
+        TyKind::Uint(UintTy::U128) => pick!(u128),
+        TyKind::Uint(UintTy::Usize) => pick!(usize),
+        TyKind::Int(IntTy::I8) => pick!(i8),
+        TyKind::Int(IntTy::I16) => pick!(i16),
+        TyKind::Int(IntTy::I32) => pick!(i32),
+        TyKind::Int(IntTy::I64) => pick!(i64),
+        TyKind::Int(IntTy::I128) => pick!(i128),
+        TyKind::Int(IntTy::Isize) => pick!(isize),
         // Ty::Slice(ty) => {
         //     let n = arbitrary::<usize>() % 6;
         //     let values: Vec<_> =
@@ -319,35 +450,89 @@ fn generate_for_type(seed: u64, ty: &Ty) -> (serde_json::Value, String) {
 }
 
 impl ContractPool<ParametricContracts> {
-    pub fn instantiate_values(mut self) -> ContractPool<InstantiatedContracts> {
+    pub fn instantiate_values(
+        mut self,
+    ) -> Result<ContractPool<InstantiatedContracts>, crate::Error> {
         let mut instantiated_contracts = vec![];
-        for (i, contract) in self.contracts.iter().enumerate() {
-            let mut next_seed = {
-                let mut current = contract.seed.unwrap_or_else(|| {
-                    use std::hash::{Hash, Hasher};
-                    let mut hasher = std::collections::hash_map::DefaultHasher::new();
-                    contract.hash(&mut hasher);
-                    hasher.finish()
-                });
-                move || (current, current += 1).0
-            };
-            let mut instances = vec![];
-            for _ in 1..(contract.tests * 20) {
+        for i in 0..self.contracts.len() {
+            let strategy = self.contracts[i].strategy;
+            instantiated_contracts.extend(self.sample_instances(i, 0, strategy)?);
+        }
+        Ok(ContractPool {
+            contracts: instantiated_contracts,
+            state: InstantiatedContracts,
+        })
+    }
+
+    /// Instantiates the `i`th contract with up to `tests` sets of
+    /// values satisfying its precondition. Each `round` draws values
+    /// from fresh seeds: round `0` uses the seeds of the contract.
+    /// Fails if the precondition panics.
+    fn sample_instances(
+        &mut self,
+        i: usize,
+        round: u64,
+        strategy: Strategy,
+    ) -> Result<Vec<Contract>, crate::Error> {
+        let contract = &self.contracts[i];
+        let mut next_seed = {
+            let mut current = contract.seed.unwrap_or_else(|| {
+                use std::hash::{Hash, Hasher};
+                let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                contract.hash(&mut hasher);
+                hasher.finish()
+            });
+            current = current.wrapping_add(round * (contract.tests as u64) * 20);
+            move || (current, current = current.wrapping_add(1)).0
+        };
+        let mut instances = vec![];
+        let mut tries = (contract.tests * 20).saturating_sub(1);
+        while instances.len() < contract.tests && tries > 0 {
+            let types = &self.state.types[i];
+            let batch: Vec<Vec<(serde_json::Value, String)>> = (0..tries.min(PRECONDITION_BATCH))
+                .map(|_| {
+                    types
+                        .iter()
+                        .map(|ty| generate_for_type(next_seed(), ty, strategy))
+                        .collect()
+                })
+                .collect();
+            tries -= batch.len();
+            let results = self.state.test_preconditions(
+                i,
+                batch
+                    .iter()
+                    .map(|values| values.iter().map(|(repr, _)| repr.clone()).collect())
+                    .collect(),
+            );
+            for (values, result) in batch.into_iter().zip(results) {
                 if instances.len() >= contract.tests {
                     break;
                 }
-                let types = &self.state.types[i];
-                let values = {
-                    types
-                        .iter()
-                        .map(|ty| generate_for_type(next_seed(), ty))
-                        .collect::<Vec<(serde_json::Value, String)>>()
-                };
-                let Some(result) = self.state.test_precondition(
-                    i,
-                    values.clone().into_iter().map(|(repr, _)| repr).collect(),
-                ) else {
-                    panic!("Precondition panicked!")
+                let result = match result {
+                    Ok(Some(result)) => result,
+                    Ok(None) => {
+                        return Err(crate::Error::PreconditionPanic {
+                            contract: contract.into(),
+                            inputs: values.iter().map(|(_, rust_expr)| rust_expr).join(", "),
+                        })
+                    }
+                    Err(err @ (ServerError::InvalidResponse { .. } | ServerError::Restart(_))) => {
+                        return Err(crate::Error::Server {
+                            contract: contract.into(),
+                            message: err.to_string(),
+                        })
+                    }
+                    Err(err) => {
+                        eprintln!(
+                            "{}: {} for contract `{}` on inputs ({}): {err}",
+                            contract.span,
+                            "Precondition diverged".yellow().bold(),
+                            contract.description,
+                            values.iter().map(|(_, rust_expr)| rust_expr).join(", "),
+                        );
+                        continue;
+                    }
                 };
                 if result {
                     let mut new_contract = contract.clone();
@@ -361,19 +546,143 @@ impl ContractPool<ParametricContracts> {
                     instances.push(new_contract);
                 }
             }
-            instantiated_contracts.extend(instances);
         }
-        ContractPool {
+        Ok(instances)
+    }
+
+    /// Instantiates values guided by coverage: for each function
+    /// tested by the contracts, fresh instances are drawn round after
+    /// round (alternating between the strategy of the contract and
+    /// the `Boundary` strategy). An instance is kept only if it covers
+    /// lines or branches of the function that were not covered
+    /// before. This stops when the function is fully covered, or
+    /// after `budget` rounds. Finally, only a minimal set of
+    /// instances covering the same lines and branches is kept.
+    ///
+    /// Contracts that don't test a function are instantiated as with
+    /// `instantiate_values`. Note that the eval nodes of the resulting
+    /// contracts are already computed.
+    pub fn instantiate_values_guided(
+        mut self,
+        backend: &dyn CoverageBackend,
+        budget: usize,
+    ) -> Result<ContractPool<InstantiatedContracts>, crate::Error> {
+        let dependencies = self.dependencies();
+        let by_functions_tested: Vec<(Option<TestedFunction>, Vec<usize>)> =
+            (0..self.contracts.len())
+                .map(|i| (self.contracts[i].function_tested(), i))
+                .into_group_map()
+                .into_iter()
+                .sorted()
+                .collect();
+
+        let mut instantiated_contracts = vec![];
+        for (fn_path, indexes) in by_functions_tested {
+            let Some(fn_path) = fn_path else {
+                for i in indexes {
+                    let strategy = self.contracts[i].strategy;
+                    instantiated_contracts.extend(self.sample_instances(i, 0, strategy)?);
+                }
+                continue;
+            };
+            trace!("fn_path={:?}", fn_path);
+            let harness = Harness::new(&fn_path, &dependencies)?;
+            let mut all_points: Option<HashSet<CoveragePoint>> = None;
+            let mut covered = HashSet::new();
+            let mut kept: Vec<(Contract, HashSet<CoveragePoint>)> = vec![];
+            let mut first_round = vec![];
+            'rounds: for round in 0..budget.max(1) {
+                let mut candidates = ContractPool {
+                    contracts: vec![],
+                    state: InstantiatedContracts,
+                };
+                for &i in &indexes {
+                    let strategy = if round % 2 == 0 {
+                        self.contracts[i].strategy
+                    } else {
+                        Strategy::Boundary
+                    };
+                    let samples = self.sample_instances(i, round as u64, strategy)?;
+                    candidates.contracts.extend(samples);
+                }
+                candidates.compute_eval_nodes()?;
+                if round == 0 {
+                    first_round.extend(candidates.contracts.iter().cloned());
+                }
+                // The candidates of a round share a single build of the
+                // crate, but the coverage backend still runs once per
+                // candidate
+                let reports = harness.coverage_per_contract(
+                    &candidates.contracts.iter().collect::<Vec<_>>(),
+                    backend,
+                );
+                for (candidate, report) in candidates.contracts.into_iter().zip(reports) {
+                    let Some(report) = report else {
+                        continue;
+                    };
+                    let points = report.covered_points();
+                    let all_points = all_points.get_or_insert_with(|| report.points());
+                    if !points.is_subset(&covered) {
+                        covered.extend(points.iter().cloned());
+                        kept.push((candidate, points));
+                    }
+                    if all_points.is_subset(&covered) {
+                        break 'rounds;
+                    }
+                }
+                println!(
+                    "   `{}`: {}/{} points covered after round {}",
+                    fn_path,
+                    covered.len(),
+                    all_points.as_ref().map_or(0, HashSet::len),
+                    round + 1
+                );
+            }
+            if kept.is_empty() {
+                // The function is not instrumented at all: coverage
+                // cannot guide us, keep the instances of the first round
+                instantiated_contracts.extend(first_round);
+            } else {
+                instantiated_contracts.extend(minimal_covering_set(kept));
+            }
+        }
+        Ok(ContractPool {
             contracts: instantiated_contracts,
             state: InstantiatedContracts,
-        }
+        })
+    }
+}
+
+/// Greedily picks instances from `instances` until every coverage
+/// point they cover is covered, preferring instances covering the
+/// most points not covered yet.
+fn minimal_covering_set(mut instances: Vec<(Contract, HashSet<CoveragePoint>)>) -> Vec<Contract> {
+    let mut covered = HashSet::new();
+    let mut result = vec![];
+    loop {
+        let best = instances
+            .iter()
+            .enumerate()
+            .map(|(i, (_, points))| (i, points.difference(&covered).count()))
+            .filter(|(_, new_points)| *new_points > 0)
+            .max_by_key(|(i, new_points)| (*new_points, std::cmp::Reverse(*i)));
+        let Some((i, _)) = best else {
+            break;
+        };
+        let (contract, points) = instances.remove(i);
+        covered.extend(points);
+        result.push(contract);
     }
+    result
 }
 
+/// Evaluates `exprs`, returning for each expression its value as
+/// Rust source, or the message of its panic. Fails if the
+/// expressions don't compile, or if the program crashes.
 fn eval_expressions(
     exprs: &[proc_macro2::TokenStream],
     dependencies: &HashMap<String, DependencySpec>,
-) -> Result<Vec<Result<String, String>>, (String, String)> {
+) -> Result<Vec<Result<String, String>>, KrateError> {
     declare! {
         Api,
         pub mod api {
@@ -403,76 +712,105 @@ fn eval_expressions(
     let program = format!("{}", program.to_token_stream());
     krate.source(&program);
 
-    let output = krate.run().wait_with_output().unwrap();
+    let key = krate.cache_key();
+    if let Some(results) = crate::cache::get("eval", key) {
+        return Ok(results);
+    }
+
+    let binary = krate.build_release()?;
+    let output = std::process::Command::new(binary)
+        .output()
+        .map_err(KrateError::Io)?;
 
-    let stdout = std::str::from_utf8(&output.stdout).unwrap();
-    let stderr = std::str::from_utf8(&output.stderr).unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
 
-    if output.status.success() {
-        Ok(serde_json::from_str(&stdout).expect(stdout))
-    } else {
-        Err((stderr.into(), program))
+    if !output.status.success() {
+        return Err(KrateError::Crashed(format!("{}\n{stderr}", output.status)));
     }
+    let results: Vec<Result<String, String>> = serde_json::from_str(&stdout)
+        .map_err(|err| KrateError::Crashed(format!("unexpected output ({err}): {stdout}")))?;
+    crate::cache::put("eval", key, &results);
+    Ok(results)
 }
 
 impl ContractPool<InstantiatedContracts> {
-    pub fn compute_eval_nodes(&mut self) {
+    /// Computes the `eval` nodes of the contracts. Fails if a node
+    /// doesn't compile or panics.
+    pub fn compute_eval_nodes(&mut self) -> Result<(), crate::Error> {
         let mut identifiers: Vec<Vec<_>> = vec![];
         let mut nodes: Vec<_> = vec![];
-        for contract in &mut self.contracts {
+        // The index of the contract of each node
+        let mut owners: Vec<usize> = vec![];
+        for (nth, contract) in self.contracts.iter_mut().enumerate() {
             let eval_nodes = contract.extract_eval_nodes();
-            let (contract_ids, contract_nodes): (Vec<_>, Vec<_>) = eval_nodes.into_iter().unzip();
-            identifiers.push(contract_ids);
+            identifiers.push(
+                eval_nodes
+                    .iter()
+                    .map(|(_, identifier, _)| identifier.clone())
+                    .collect(),
+            );
             let use_statements = &contract.use_statements;
-            let contract_nodes: Vec<_> = contract_nodes
+            let contract_nodes: Vec<_> = eval_nodes
                 .into_iter()
-                .map(|node| {
+                .map(|(field, _, node)| {
+                    // Errors are located in the field the node comes from
+                    let node = mark(nth, field, node);
                     quote! {
                         #(use #use_statements;)*
                         #node
                     }
                 })
                 .collect();
+            owners.extend(contract_nodes.iter().map(|_| nth));
             nodes.extend(contract_nodes);
         }
 
         if nodes.is_empty() {
-            return;
+            return Ok(());
         }
 
         let dependencies = self.dependencies();
         let nodes = run_or_locate_error(&nodes, |node| eval_expressions(node, &dependencies))
-            .unwrap_or_else(|(context, (stderr, program))| {
-                eprintln!(
-                    "{:#?}",
-                    context
-                        .iter()
-                        .map(|x| format!("{}", x.into_token_stream()))
-                        .collect::<Vec<_>>()
-                );
-                eprintln!("> stderr: {stderr}");
-                eprintln!("> program: {program}");
-                panic!()
-            });
+            .map_err(|(context, error)| {
+                let contracts: Vec<_> = self.contracts.iter().collect();
+                let failed = context
+                    .iter()
+                    .map(|node| {
+                        let index = nodes.iter().position(|n| std::ptr::eq(n, *node)).unwrap();
+                        owners[index]
+                    })
+                    .unique()
+                    .map(|nth| contracts[nth].into())
+                    .collect();
+                error.into_error(failed, &contracts)
+            })?;
 
         let mut cursor = 0;
         for (contract, identifiers) in self.contracts.iter_mut().zip(identifiers.iter()) {
             let mut substs = HashMap::new();
             for identifier in identifiers {
-                let node = nodes[cursor].clone().unwrap();
+                let node = nodes[cursor]
+                    .clone()
+                    .map_err(|message| crate::Error::Eval {
+                        contract: (&*contract).into(),
+                        message,
+                    })?;
                 substs.insert(identifier.clone(), syn::parse_str(&node).unwrap());
                 cursor += 1;
             }
             contract.subst_names_with_exprs(substs);
         }
+        Ok(())
     }
 
-    /// Computes coverage information for a crate using both `tarpaulin` and `hax`.
+    /// Computes coverage information for a crate using both a coverage
+    /// backend (e.g. `tarpaulin`) and `hax`.
     ///
     /// This function combines data from two tools to generate precise
     /// metrics:
     ///
-    /// - **`tarpaulin`**: Provides line-by-line coverage information
+    /// - **`tarpaulin`** (or another backend): Provides line-by-line coverage information
     /// (covered/uncovered) for all files within a crate. However,
     /// this raw data includes irrelevant coverage details outside the
     /// scope of the function being tested. For example, when testing
@@ -492,16 +830,20 @@ impl ContractPool<InstantiatedContracts> {
     ///    - Duplicate the crate to safely inject additional test cases and formatting changes.
     /// 2. Query `hax` to determine the precise span of the function under test.
     /// 3. Use the span to:
-    ///    - Insert a unit test for the function directly after its definition.
+    ///    - Insert a test module for the function directly after the item defining it.
     ///    - Format the crate to align the control-flow branches for better per-line coverage analysis.
-    /// 4. Run `tarpaulin` to generate a coverage report filtered to the span of the function under test.
+    /// 4. Run the coverage backend to generate a coverage report filtered to the span of the function under test.
     ///
     /// ### Returns
-    /// A vector of `BadCoverageReport`, each representing uncovered lines for the
-    /// functions tested by the contracts.
+    /// A vector of `BadCoverageReport`, one for each function tested
+    /// by the contracts. Reports for functions that are not entirely
+    /// covered are printed.
     #[tracing::instrument]
-    pub fn compute_coverage(&self) -> Vec<crate::krate::tarpaulin::BadCoverageReport> {
-        let by_functions_tested: HashMap<Vec<String>, Vec<&Contract>> = self
+    pub fn compute_coverage(
+        &self,
+        backend: &dyn CoverageBackend,
+    ) -> Result<Vec<crate::krate::coverage::BadCoverageReport>, crate::Error> {
+        let by_functions_tested: HashMap<TestedFunction, Vec<&Contract>> = self
             .contracts
             .iter()
             .flat_map(|contract| Some((contract.function_tested()?, contract)))
@@ -509,114 +851,68 @@ impl ContractPool<InstantiatedContracts> {
 
         by_functions_tested
             .into_iter()
-            .filter_map(|(fn_path, contracts)| {
+            .map(|(fn_path, contracts)| {
                 trace!("fn_path={:?}", fn_path);
                 // The contracts in `contracts` are all about the same
                 // function `fn_path`.
+                Ok(Harness::new(&fn_path, &self.dependencies())?.coverage(&contracts, backend))
+            })
+            .filter_map(Result::transpose)
+            .inspect(|report| {
+                if let Ok(report) = report {
+                    if !report.is_fully_covered() {
+                        println!("{report}")
+                    }
+                }
+            })
+            .collect()
+    }
 
-                // The crate of the function we're testing
-                let krate_name = &fn_path[0];
-
-                let assertions: Vec<_> = contracts
-                    .iter()
-                    .map(|contract| contract.as_assertion())
-                    .collect();
-
-                let krate = {
-                    // Find the full path to the source of the crate `krate_name`.
-                    let krate_path = {
-                        // `krate` is a dummy crate whose dependencies
-                        // are matching dependencies declared in the
-                        // contracts `contracts`
-                        let krate = {
-                            let mut krate = Krate::new();
-                            krate.add_dependencies(&self.dependencies());
-                            krate
-                        };
-                        // Runs `cargo metadata`, and finds the path
-                        // to the `Cargo.toml` of the crate `krate_name`.
-                        let manifest_path = krate
-                            .manifest_path_of_crate(krate_name)
-                            .expect(&format!("Could not find {krate_name}: please make sure your contract declares a dependency on that crate. The dependencies currently available are: {:#?}.", self.dependencies()));
-                        // Returns the parent folder of the `Cargo.toml` manifest
-                        manifest_path.parent().unwrap().to_path_buf()
-                    };
-                    // We duplicate the crate `krate_name` so that we can edit it freely
-                    Krate::duplicate_crate(&krate_path, &self.dependencies().into_iter().collect())
-                        .unwrap()
-                };
-
-                // Stringify the path
-                let fn_path = fn_path.join("::");
-
-                // Ask hax about the span of the item `fn_path`
-                let span = {
-                    let items = krate.hax().unwrap();
-                    let item = items
-                        .iter()
-                        .find(|item| {
-                            let mut owner_id =
-                                (&item.owner_id as &hax_frontend_exporter::DefIdContents).clone();
-                            owner_id.krate = krate_name.to_string();
-                            owner_id.into_string() == fn_path
-                        })
-                        .unwrap();
-                    item.span.clone()
-                };
-
-                // Reconstruct the full path to the Rust file holding the item `fn_path`
-                let filepath = krate
-                    .workspace_path()
-                    .join(span.filename.to_path().unwrap());
-
-                // Construct a unit test that runs the assertions
-                let test_function = {
-                    let test = quote! {
-                        #[test]
-                        fn testify_test() {
-                            #(#assertions)*
-                        }
-                    };
-                    let test = format!("{}", test.to_token_stream());
-                    test.replace(krate_name, "crate")
-                };
+    /// Measures the strength of the contracts with mutation testing:
+    /// for each function tested by the contracts, mutants of the
+    /// function are generated within its hax span, and the assertions
+    /// of the contracts are run against each of them. Surviving
+    /// mutants are printed.
+    ///
+    /// Functions whose assertions don't pass on the original code
+    /// are skipped.
+    #[tracing::instrument]
+    pub fn mutation_testing(
+        &self,
+        timeout: std::time::Duration,
+    ) -> Result<Vec<crate::krate::mutation::MutationReport>, crate::Error> {
+        let by_functions_tested: HashMap<TestedFunction, Vec<&Contract>> = self
+            .contracts
+            .iter()
+            .flat_map(|contract| Some((contract.function_tested()?, contract)))
+            .into_group_map();
 
-                // Insert the unit test right after the item `fn_path` in place
-                {
-                    use std::fs;
-                    let contents = fs::read_to_string(&filepath).unwrap();
-                    let (before, after) = contents.split_at_loc(span.hi.clone());
-                    fs::write(&filepath, format!("{before}{test_function}{after}")).unwrap();
+        by_functions_tested
+            .into_iter()
+            .map(|(fn_path, contracts)| {
+                trace!("fn_path={:?}", fn_path);
+                let report = Harness::new(&fn_path, &self.dependencies())?
+                    .mutation_testing(&contracts, timeout);
+                if report.is_none() {
+                    println!(
+                        "{}",
+                        format!("Skipping mutation testing of `{fn_path}`: its tests don't pass.")
+                            .yellow()
+                    );
+                }
+                Ok(report)
+            })
+            .filter_map(Result::transpose)
+            .inspect(|report| {
+                if let Ok(report) = report {
+                    println!("{report}")
                 }
-
-                // Format the crate: we want the various control-flow
-                // branches to be on their own line, `tarpaulin` gives
-                // a per-line report
-                krate.fmt().unwrap();
-
-                // Ask tarpaulin a coverage report, but keep only the
-                // reports that are within the span `span`
-                let report =
-                    krate
-                        .tarpaulin()
-                        .coverage_for_span(fn_path.to_string(), &filepath, span);
-                trace!("report={:?}", report);
-                report
             })
-            .inspect(|report| println!("{report}"))
             .collect()
     }
 }
 
 impl<State: IsState> ContractPool<State> {
-    fn retype<NextState: IsState>(self, state: NextState) -> Option<ContractPool<NextState>> {
-        let Self {
-            contracts,
-            state: _,
-        } = self;
-        let pool = ContractPool { contracts, state };
-        pool.check().then_some(pool)
-    }
     pub fn check(&self) -> bool {
         self.contracts.iter().all(State::check)
     }
@@ -648,3 +944,29 @@ impl<State: IsState> ContractPool<State> {
         dependencies_to_string(&self.dependencies())
     }
 }
+
+#[test]
+fn boundary_strategy_keeps_random_values_unbiased() {
+    let random: Vec<u8> = (0..256)
+        .filter(|seed| boundary_pick(*seed).is_none())
+        .map(arbitrary_with_seed::<u8>)
+        .collect();
+    assert!(!random.is_empty() && random.len() < 256);
+    assert!(random.iter().any(|value| value % 2 == 0));
+    assert!(random.iter().any(|value| value % 2 == 1));
+}
+
+#[test]
+fn json_keeps_spans() {
+    let source = "[[contracts]]\ndescription = \"\"\nfunction_tested = \"krate::f\"\npostcondition = \"true\"\n";
+    let contracts = crate::contracts_file::parse(source, Some(Path::new("a.contracts.toml")))
+        .unwrap()
+        .contracts;
+    let pool = ContractPool::from_json(&ContractPool::new(contracts).to_json());
+    let contract = &pool.contracts()[0];
+    assert_eq!(contract.span.to_string(), "a.contracts.toml:1:1");
+    assert_eq!(
+        contract.field_spans["postcondition"].to_string(),
+        "a.contracts.toml:4:17"
+    );
+}
@@ -17,4 +17,4 @@ pub use thiserror::Error;
 pub use tracing::trace;
 
 pub use crate::DependencySpec;
-pub use crate::{Contract, Input, InputKind, Span};
+pub use crate::{Contract, Input, InputKind, Span, Strategy};
@@ -62,10 +62,7 @@ impl<T> SubstVisitor<T> {
     fn pattern_contains_binding(&self, pat: &syn::Pat) -> bool {
         let mut visitor = IdentCollector::default();
         visitor.visit_pat(&pat);
-        visitor
-            .idents()
-            .iter()
-            .any(|i| i.to_string() == self.binding)
+        visitor.bound.iter().any(|i| *i == self.binding)
     }
 }
 
@@ -118,26 +115,39 @@ impl VisitMut for SubstVisitor<syn::Expr> {
     }
 }
 
+/// Collects the identifiers used as expressions (e.g. variables or
+/// called functions), and the ones bound by patterns (e.g. closure
+/// parameters or `let` bindings). Paths in types, such as `u32` in
+/// `x as u32` or `u8` in `Vec::<u8>::new()`, are not collected, and
+/// neither are paths with several segments (e.g. `u8::MAX`).
 #[derive(Debug, Clone, Default)]
 pub struct IdentCollector {
     idents: HashSet<syn::Ident>,
+    bound: HashSet<syn::Ident>,
 }
 
 impl IdentCollector {
-    pub fn idents(self) -> HashSet<syn::Ident> {
-        self.idents
+    /// The identifiers used as expressions, and not bound by any
+    /// pattern.
+    pub fn free_idents(self) -> HashSet<syn::Ident> {
+        &self.idents - &self.bound
     }
 }
 
 impl<'a> Visit<'a> for IdentCollector {
-    fn visit_path(&mut self, path: &'a syn::Path) {
-        if let Some(ident) = path.expect_ident() {
+    fn visit_expr_path(&mut self, expr: &'a syn::ExprPath) {
+        if let (None, Some(ident)) = (&expr.qself, expr.path.expect_ident()) {
             self.idents.insert(ident);
         };
-        // We need to recurse under paths, e.g. `f::<x>` refers to
+        // We need to recurse under paths, e.g. `f::<{ x }>` refers to
         // both `f` and `x`
-        visit_path(self, path);
+        visit_expr_path(self, expr);
+    }
+    fn visit_pat_ident(&mut self, pat: &'a syn::PatIdent) {
+        self.bound.insert(pat.ident.clone());
+        visit_pat_ident(self, pat);
     }
+    fn visit_type(&mut self, _: &'a syn::Type) {}
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -385,6 +395,11 @@ impl PartialCompute {
         self.compute_queue
     }
 
+    /// The number of nodes extracted so far.
+    pub fn node_count(&self) -> usize {
+        self.compute_queue.len()
+    }
+
     fn partial_compute(&mut self, expr: &syn::Expr) -> syn::Expr {
         let bindings = self.bindings.clone();
 
@@ -0,0 +1,206 @@
+//! This module provides an overview of the public functions of a
+//! crate: for each of them, how many contracts test it, how many
+//! tests were generated, and how well they are covered. This helps
+//! finding the functions that are not tested by any contract.
+use crate::coverage::CoverageBackend;
+use crate::krate::{harness::test_module, Krate};
+use crate::pool::ContractPool;
+use crate::prelude::*;
+use crate::TestedFunction;
+use std::fmt;
+
+/// The summary of a public function.
+#[derive(Clone, Debug, Serialize)]
+pub struct FunctionSummary {
+    /// The path of the function.
+    pub function: String,
+    /// The number of contracts testing the function.
+    pub contracts: usize,
+    /// The number of tests generated out of those contracts.
+    pub instances: usize,
+    /// The line coverage (in percent) of the function when running
+    /// every test generated for the crate, if any line of the
+    /// function is instrumented.
+    pub line_coverage: Option<f64>,
+}
+
+/// The summaries of the public functions of some crates.
+#[derive(Clone, Debug, Serialize)]
+#[serde(transparent)]
+pub struct CoverageSummary(pub Vec<FunctionSummary>);
+
+/// Summarizes the public functions of the crates `krates`. When
+/// `krates` is empty, the crates of the functions tested by
+/// `contracts` are summarized.
+///
+/// The contracts are instantiated, and the tests generated for a
+/// crate are run all at once, so that coverage is computed with a
+/// single run of the coverage backend per crate.
+pub fn coverage_summary(
+    contracts: Vec<Contract>,
+    krates: &[String],
+    backend: &dyn CoverageBackend,
+) -> Result<CoverageSummary, crate::Error> {
+    let krates: Vec<String> = if krates.is_empty() {
+        contracts
+            .iter()
+            .flat_map(|contract| Some(contract.function_tested()?.krate().to_string()))
+            .unique()
+            .collect()
+    } else {
+        krates.to_vec()
+    };
+
+    let mut dependencies = HashMap::new();
+    let mut instances = vec![];
+    for pool in ContractPool::new_pools(contracts.clone()) {
+        dependencies.extend(pool.dependencies());
+        let mut pool = pool.instantiate_types()?.instantiate_values()?;
+        pool.compute_eval_nodes()?;
+        instances.extend(pool.contracts().iter().cloned());
+    }
+
+    let mut summaries = vec![];
+    for krate_name in &krates {
+        summaries.extend(summarize_crate(
+            krate_name,
+            &contracts,
+            &instances,
+            &dependencies,
+            backend,
+        )?);
+    }
+    Ok(CoverageSummary(summaries))
+}
+
+/// Summarizes the public functions of the crate `krate_name`. Fails
+/// if the crate has no library, e.g. if it is a binary.
+fn summarize_crate(
+    krate_name: &str,
+    contracts: &[Contract],
+    instances: &[Contract],
+    dependencies: &HashMap<String, DependencySpec>,
+    backend: &dyn CoverageBackend,
+) -> Result<Vec<FunctionSummary>, crate::Error> {
+    let (krate, _) = Krate::duplicate_dependency(krate_name, dependencies)?;
+    let workdir = krate.workspace_path();
+    let items: Vec<_> = krate
+        .hax()
+        .map_err(|error| error.into_error(vec![], &[]))?
+        .iter()
+        .flat_map(|item| item.nested_items())
+        .collect();
+
+    let testing_crate = |contract: &&Contract| {
+        contract
+            .function_tested()
+            .is_some_and(|tested| tested.krate() == krate_name)
+    };
+    let tests = |item: &NestedItem, contracts: &[Contract]| {
+        contracts
+            .iter()
+            .filter(testing_crate)
+            .filter(|contract| contract.function_tested().unwrap().matches(item, &workdir))
+            .count()
+    };
+
+    // Run every test generated for the crate at once. The test
+    // module is appended to the root of the crate, so that the spans
+    // of the items are left untouched.
+    let coverage = {
+        let test_module = test_module(krate_name, instances.iter().filter(testing_crate));
+        let lib_path = krate
+            .lib_path()
+            .ok_or_else(|| crate::Error::MissingLibrary(krate_name.to_string()))?;
+        let source = std::fs::read_to_string(&lib_path)?;
+        std::fs::write(&lib_path, format!("{source}\n{test_module}\n"))?;
+        backend.coverage(&krate, "testify_test")
+    };
+
+    Ok(items
+        .iter()
+        .filter_map(|item| {
+            let function = TestedFunction::of_item(item, krate_name, &workdir)?;
+            is_public_fn(item, &function, &workdir).then_some((item, function))
+        })
+        .map(|(item, function)| {
+            let line_coverage = item
+                .span
+                .filename
+                .to_path()
+                .and_then(|path| {
+                    coverage.coverage_for_span(
+                        function.to_string(),
+                        &workdir.join(path),
+                        item.span.clone(),
+                    )
+                })
+                .map(|report| report.coverage_percent());
+            FunctionSummary {
+                function: function.to_string(),
+                contracts: tests(item, contracts),
+                instances: tests(item, instances),
+                line_coverage,
+            }
+        })
+        .collect())
+}
+
+/// Whether `item` is a public function or a public method. Methods
+/// of trait implementations are considered public.
+fn is_public_fn(item: &NestedItem, function: &TestedFunction, workdir: &Path) -> bool {
+    let Some(source) = item.span.source(workdir) else {
+        return false;
+    };
+    let is_public = |vis: &syn::Visibility| matches!(vis, syn::Visibility::Public(_));
+    if let Ok(item_fn) = syn::parse_str::<syn::ItemFn>(&source) {
+        return is_public(&item_fn.vis);
+    }
+    if let Ok(impl_item_fn) = syn::parse_str::<syn::ImplItemFn>(&source) {
+        return is_public(&impl_item_fn.vis) || function.as_trait.is_some();
+    }
+    false
+}
+
+impl fmt::Display for CoverageSummary {
+    /// Formats the summary as a table.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .0
+            .iter()
+            .map(|summary| summary.function.len())
+            .chain(["Function".len()])
+            .max()
+            .unwrap_or(0);
+        writeln!(
+            f,
+            "{}",
+            format!(
+                "{:width$}  {:>9}  {:>9}  {:>13}",
+                "Function", "Contracts", "Instances", "Line coverage"
+            )
+            .bold()
+        )?;
+        for summary in &self.0 {
+            let coverage = summary
+                .line_coverage
+                .map_or("-".to_string(), |coverage| format!("{coverage:.1}%"));
+            let row = format!(
+                "{:width$}  {:>9}  {:>9}  {:>13}",
+                summary.function, summary.contracts, summary.instances, coverage
+            );
+            let row = match summary.contracts {
+                0 => row.red(),
+                _ if summary
+                    .line_coverage
+                    .is_some_and(|coverage| coverage < 100.0) =>
+                {
+                    row.yellow()
+                }
+                _ => row.green(),
+            };
+            writeln!(f, "{row}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+//! This module resolves the `function_tested` path of a contract to
+//! an item exported by hax. Supported paths are plain functions
+//! (`krate::module::f`), generic functions (`krate::f::<u8>`),
+//! inherent methods (`krate::Type::method`) and trait methods on a
+//! named impl (`<krate::Type as Trait>::method`).
+use crate::prelude::*;
+use std::fmt;
+
+/// A normalized path to the function tested by a contract.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TestedFunction {
+    /// The segments of the path, with generic arguments erased. For
+    /// a method, the segment before the name of the method is the
+    /// name of the type of the `impl` block.
+    pub segments: Vec<String>,
+    /// For a qualified path `<Type as Trait>::method`, the name of
+    /// the trait.
+    pub as_trait: Option<String>,
+}
+
+impl TestedFunction {
+    /// Normalizes a path. For a qualified path, the path of the type
+    /// is expected to start with the name of its crate. Fails if the
+    /// path is qualified by a type which is not a path, e.g.
+    /// `<[u8]>::len`.
+    pub fn new(path: &syn::ExprPath) -> Result<Self, crate::Error> {
+        let idents = |segments: &mut dyn Iterator<Item = &syn::PathSegment>| {
+            segments
+                .map(|segment| segment.ident.to_string())
+                .collect::<Vec<_>>()
+        };
+        let Some(qself) = &path.qself else {
+            return Ok(Self {
+                segments: idents(&mut path.path.segments.iter()),
+                as_trait: None,
+            });
+        };
+        let syn::Type::Path(self_ty) = qself.ty.as_ref() else {
+            return Err(crate::Error::UnsupportedFunction {
+                function: path.to_token_stream().to_string().replace(' ', ""),
+                reason: format!(
+                    "the type `{}` is not a path",
+                    qself.ty.to_token_stream().to_string().replace(' ', "")
+                ),
+            });
+        };
+        let as_trait = qself
+            .position
+            .checked_sub(1)
+            .map(|nth| path.path.segments[nth].ident.to_string());
+        Ok(Self {
+            segments: idents(
+                &mut self_ty
+                    .path
+                    .segments
+                    .iter()
+                    .chain(path.path.segments.iter().skip(qself.position)),
+            ),
+            as_trait,
+        })
+    }
+
+    /// The name of the crate defining the function.
+    pub fn krate(&self) -> &str {
+        &self.segments[0]
+    }
+
+    /// Finds the item of the function among the items of a
+    /// duplicate of the crate `self.krate()`, whose sources are in `workdir`.
+    pub fn resolve(
+        &self,
+        items: &[hax_frontend_exporter::Item<hax_frontend_exporter::ThirBody>],
+        workdir: &Path,
+    ) -> Option<NestedItem> {
+        items
+            .iter()
+            .flat_map(|item| item.nested_items())
+            .find(|item| self.matches(item, workdir))
+    }
+
+    /// Whether `item` is the function. A path which is not
+    /// qualified by a trait also matches trait methods.
+    pub fn matches(&self, item: &NestedItem, workdir: &Path) -> bool {
+        Self::of_item(item, self.krate(), workdir).is_some_and(|candidate| {
+            candidate.segments == self.segments
+                && (self.as_trait.is_none() || self.as_trait == candidate.as_trait)
+        })
+    }
+
+    /// Computes the normalized path of `item`, an item of a duplicate
+    /// of the crate `krate_name`, whose sources are in `workdir`.
+    /// Returns `None` for items within `impl` blocks that cannot be
+    /// parsed.
+    pub fn of_item(item: &NestedItem, krate_name: &str, workdir: &Path) -> Option<Self> {
+        let mut owner_id = (&item.owner_id as &hax_frontend_exporter::DefIdContents).clone();
+        owner_id.krate = krate_name.to_string();
+        let path: Vec<String> = owner_id
+            .into_string()
+            .split("::")
+            .map(str::to_string)
+            .collect();
+        if !path.iter().any(|segment| segment == "r#impl") {
+            return Some(Self {
+                segments: path,
+                as_trait: None,
+            });
+        }
+        // The item is within an `impl` block: we look at its header
+        // to find out the names of the type and of the trait
+        let (self_ty, as_trait) = impl_header(item.parent_span.as_ref()?, workdir)?;
+        let segments = path
+            .into_iter()
+            .map(|segment| {
+                if segment == "r#impl" {
+                    self_ty.clone()
+                } else {
+                    segment
+                }
+            })
+            .collect();
+        Some(Self { segments, as_trait })
+    }
+}
+
+/// Parses the `impl` block at `span`, returning the name of its type
+/// and the name of its trait, if any.
+fn impl_header(
+    span: &hax_frontend_exporter::Span,
+    workdir: &Path,
+) -> Option<(String, Option<String>)> {
+    let item: syn::ItemImpl = syn::parse_str(&span.source(workdir)?).ok()?;
+    let last_ident = |path: &syn::Path| Some(path.segments.last()?.ident.to_string());
+    let syn::Type::Path(self_ty) = item.self_ty.as_ref() else {
+        return None;
+    };
+    Some((
+        last_ident(&self_ty.path)?,
+        item.trait_
+            .as_ref()
+            .and_then(|(_, path, _)| last_ident(path)),
+    ))
+}
+
+impl fmt::Display for TestedFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.as_trait, &self.segments[..]) {
+            (Some(as_trait), [ty @ .., name]) => {
+                write!(f, "<{} as {as_trait}>::{name}", ty.join("::"))
+            }
+            _ => write!(f, "{}", self.segments.join("::")),
+        }
+    }
+}
+
+#[test]
+fn normalization() {
+    let normalize = |path: syn::ExprPath| TestedFunction::new(&path).unwrap().to_string();
+    assert_eq!(normalize(parse_quote! {krate::m::f}), "krate::m::f");
+    assert_eq!(normalize(parse_quote! {krate::f::<u8>}), "krate::f");
+    assert_eq!(
+        normalize(parse_quote! {krate::Wrapper::<u8>::get}),
+        "krate::Wrapper::get"
+    );
+    assert_eq!(
+        normalize(parse_quote! {<krate::m::Wrapper<u8> as Default>::default}),
+        "<krate::m::Wrapper as Default>::default"
+    );
+    assert!(matches!(
+        TestedFunction::new(&parse_quote! {<[u8]>::len}),
+        Err(crate::Error::UnsupportedFunction { .. })
+    ));
+}
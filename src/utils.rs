@@ -32,24 +32,84 @@ impl hax_frontend_exporter::DefIdContents {
     }
 }
 
+/// Finds the `def_id`s mentionned in a JSON value.
+fn def_ids_in_json(json: serde_json::Value) -> Vec<hax_frontend_exporter::DefId> {
+    use serde_json::Value;
+    let mut def_ids: Vec<hax_frontend_exporter::DefId> = vec![];
+    let mut queue = vec![json];
+    while let Some(json) = queue.pop() {
+        if let Ok(def_id) = serde_json::from_value(json.clone()) {
+            def_ids.push(def_id);
+        };
+        match json {
+            Value::Null | Value::Number(_) | Value::String(_) | Value::Bool(_) => (),
+            Value::Array(values) => queue.extend(values),
+            Value::Object(map) => queue.extend(map.values().cloned()),
+        }
+    }
+    def_ids
+}
+
+/// An item as exported by hax, or an item nested in such an item
+/// (e.g. a method of an `impl` block).
+#[derive(Clone, Debug)]
+pub struct NestedItem {
+    pub owner_id: hax_frontend_exporter::DefId,
+    pub span: hax_frontend_exporter::Span,
+    /// The span of the item this item is nested in, if any.
+    pub parent_span: Option<hax_frontend_exporter::Span>,
+    /// The JSON representation of the item.
+    pub json: serde_json::Value,
+}
+
+impl NestedItem {
+    /// Finds the `def_id`s mentionned in the item.
+    pub fn def_ids(&self) -> Vec<hax_frontend_exporter::DefId> {
+        def_ids_in_json(self.json.clone())
+    }
+}
+
 #[extension(pub trait ItemExt)]
 impl<B: hax_frontend_exporter::IsBody + Serialize> hax_frontend_exporter::Item<B> {
     /// Finds the `def_id`s mentionned in an item.
     fn def_ids(&self) -> Vec<hax_frontend_exporter::DefId> {
+        def_ids_in_json(serde_json::to_value(self).unwrap())
+    }
+
+    /// Lists the item itself and every item nested in it. Nested
+    /// items are the JSON objects with both an `owner_id` and a
+    /// `span`, e.g. the methods of an `impl` block.
+    fn nested_items(&self) -> Vec<NestedItem> {
         use serde_json::Value;
-        let mut def_ids: Vec<hax_frontend_exporter::DefId> = vec![];
-        let mut queue = vec![serde_json::to_value(self).unwrap()];
-        while let Some(json) = queue.pop() {
-            if let Ok(def_id) = serde_json::from_value(json.clone()) {
-                def_ids.push(def_id);
-            };
+        #[derive(Deserialize)]
+        struct Owned {
+            owner_id: hax_frontend_exporter::DefId,
+            span: hax_frontend_exporter::Span,
+        }
+        fn walk(
+            json: &Value,
+            parent: Option<&hax_frontend_exporter::Span>,
+            out: &mut Vec<NestedItem>,
+        ) {
+            let mut parent = parent.cloned();
+            if let Ok(Owned { owner_id, span }) = Owned::deserialize(json) {
+                out.push(NestedItem {
+                    owner_id,
+                    span: span.clone(),
+                    parent_span: parent,
+                    json: json.clone(),
+                });
+                parent = Some(span);
+            }
             match json {
                 Value::Null | Value::Number(_) | Value::String(_) | Value::Bool(_) => (),
-                Value::Array(values) => queue.extend(values),
-                Value::Object(map) => queue.extend(map.values().cloned()),
+                Value::Array(values) => values.iter().for_each(|v| walk(v, parent.as_ref(), out)),
+                Value::Object(map) => map.values().for_each(|v| walk(v, parent.as_ref(), out)),
             }
         }
-        def_ids
+        let mut items = vec![];
+        walk(&serde_json::to_value(self).unwrap(), None, &mut items);
+        items
     }
 }
 
@@ -97,6 +157,26 @@ impl<'a> &'a str {
     fn split_at_loc(self, loc: hax_frontend_exporter::Loc) -> (String, String) {
         self.split_at_line_col(loc.line, loc.col)
     }
+    /// The byte offset of a position given by a line (1-based) and a
+    /// column (0-based, in characters). Positions past the end of a
+    /// line are clamped to the end of that line.
+    fn offset_at_line_col(self, line: usize, col: usize) -> usize {
+        assert!(line >= 1);
+        let line_start: usize = self
+            .split_inclusive('\n')
+            .take(line - 1)
+            .map(|line| line.len())
+            .sum();
+        let line_contents = self[line_start..].split('\n').next().unwrap_or("");
+        line_start
+            + line_contents
+                .char_indices()
+                .nth(col)
+                .map_or(line_contents.len(), |(offset, _)| offset)
+    }
+    fn offset_at_loc(self, loc: &hax_frontend_exporter::Loc) -> usize {
+        self.offset_at_line_col(loc.line, loc.col)
+    }
 }
 
 /// The `serde_via` module provides a mechanism for serializing and deserializing complex types
@@ -138,20 +218,12 @@ pub mod serde_via {
     }
 
     impl AutoSerdeVia for syn::Path {}
+    impl AutoSerdeVia for syn::ExprPath {}
     impl AutoSerdeVia for syn::Type {}
     impl AutoSerdeVia for syn::Expr {}
     impl AutoSerdeVia for syn::WhereClause {}
     impl AutoSerdeVia for syn::UseTree {}
 
-    impl SerdeVia for super::Span {
-        type Repr = u8;
-        fn from_repr(_: Self::Repr) -> Result<Self, impl Display> {
-            Ok::<_, &str>(Self::dummy())
-        }
-        fn to_repr(self) -> Self::Repr {
-            0
-        }
-    }
     impl<T: SerdeVia> SerdeVia for Option<T> {
         type Repr = Option<T::Repr>;
         fn from_repr(v: Self::Repr) -> Result<Self, impl Display> {
@@ -195,6 +267,56 @@ pub fn dependencies_to_string(deps: &HashMap<String, DependencySpec>) -> String
     toml::to_string(&wrapper).unwrap()
 }
 
+/// Pretty-prints `expr` (e.g. `x.up() + 1` rather than the token
+/// stream `x . up () + 1`).
+pub fn pretty_expr(expr: &syn::Expr) -> String {
+    pretty_item(parse_quote! {const _: () = #expr;}, "const _: () =")
+        .unwrap_or_else(|| expr.to_token_stream().to_string())
+}
+
+/// Pretty-prints `typ` (e.g. `Vec<u8>` rather than `Vec < u8 >`).
+pub fn pretty_type(typ: &syn::Type) -> String {
+    pretty_item(parse_quote! {type T = #typ;}, "type T =")
+        .unwrap_or_else(|| typ.to_token_stream().to_string())
+}
+
+/// Pretty-prints `bounds` on a single line (e.g. `where T: Clone`
+/// rather than `where T : Clone`).
+pub fn pretty_where_clause(bounds: &syn::WhereClause) -> String {
+    let source = prettyplease::unparse(&parse_quote! {fn f() #bounds {}});
+    // The predicates are on their own lines, between `where` and `{`
+    let predicates: Vec<_> = source
+        .lines()
+        .skip_while(|line| line.trim() != "where")
+        .skip(1)
+        .take_while(|line| !line.starts_with('{'))
+        .map(str::trim)
+        .collect();
+    match predicates.join(" ").trim_end_matches(',') {
+        "" => "where".into(),
+        predicates => format!("where {predicates}"),
+    }
+}
+
+/// Pretty-prints `item`, and strips `prefix` and the final `;`.
+fn pretty_item(item: syn::Item, prefix: &str) -> Option<String> {
+    let source = prettyplease::unparse(&syn::File {
+        shebang: None,
+        attrs: vec![],
+        items: vec![item],
+    });
+    let source = source.trim_end().strip_prefix(prefix)?.strip_suffix(';')?;
+    // Lines but the first one are indented relative to the item
+    Some(
+        source
+            .trim_start()
+            .lines()
+            .map(|line| line.strip_prefix("    ").unwrap_or(line))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    )
+}
+
 pub fn default_expr() -> syn::Expr {
     parse_quote! {true}
 }
@@ -1,9 +1,9 @@
 use syn::parse_quote;
-use testify::{Contract, Input, InputKind, Span};
+use testify::{Contract, Input, InputKind, Span, Strategy};
 
 fn main() {
     testify::driver::setup_tracing();
-    testify::driver::run(
+    let result = testify::driver::run(
         vec![Contract {
             inputs: vec![Input {
                 name: "x".to_string(),
@@ -16,6 +16,9 @@ fn main() {
             precondition: parse_quote! {x.up() + x.up() < 256u16.up()},
             postcondition: parse_quote! { example_crate::add_or_zero(x, x) == eval(u8::down(x.up() + x.up())) },
             span: Span::dummy(),
+            field_spans: Default::default(),
+            name: None,
+            tags: vec![],
             dependencies: toml::from_str(&format!(
                 r#"
 abstractions = {{path = "{}/abstractions"}}
@@ -29,8 +32,14 @@ example-crate = {{path = "{}/tests/coverage/example-crate"}}
             use_statements: vec![syn::parse_quote! {abstractions::*}],
             function_tested: Some(parse_quote! {example_crate::add_or_zero}),
             seed: None,
+            min_coverage: None,
+            strategy: Strategy::default(),
         }],
         "regressions.rs",
         true,
     );
+    if let Err(err) = result {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
 }
@@ -1,4 +1,4 @@
-use testify::{Contract, Input, InputKind, Span};
+use testify::{Contract, Input, InputKind, Span, Strategy};
 
 macro_rules! contract {
     {
@@ -37,6 +37,9 @@ macro_rules! contract {
                 precondition: syn::parse_quote!{$pre_body},
                 postcondition: syn::parse_quote!{$post_body},
                 span: Span::dummy(),
+                field_spans: Default::default(),
+                name: None,
+                tags: vec![],
                 seed: None,
                 tests: 5,
                 dependencies: toml::from_str(&format!(
@@ -48,6 +51,8 @@ abstractions = {{path = "{}/abstractions"}}
             .unwrap(),
                 use_statements: vec![syn::parse_quote!{abstractions::*}],
                 function_tested: None,
+                min_coverage: None,
+                strategy: Strategy::default(),
             })
         }
     };
@@ -2,5 +2,8 @@ mod imported;
 
 fn main() {
     testify::driver::setup_tracing();
-    testify::driver::run(imported::contracts(), "regressions.rs", false);
+    if let Err(err) = testify::driver::run(imported::contracts(), "regressions.rs", false) {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
 }